@@ -0,0 +1,202 @@
+mod prices;
+
+use std::fmt::{self, Display};
+use chrono::NaiveDate;
+use database::SqliteError;
+use investment_planner::{PricePath, Settings, SimulatedMonth, Simulation, SimulationError};
+
+pub use prices::MonthlyPrices;
+
+#[derive(Debug)]
+pub enum BacktestError {
+    Sqlite(SqliteError),
+    Parse(String),
+    MissingPrices(String),
+    Simulation(SimulationError),
+}
+
+impl Display for BacktestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BacktestError::Sqlite(e) => write!(f, "database error: {e}"),
+            BacktestError::Parse(message) => write!(f, "could not parse {message}"),
+            BacktestError::MissingPrices(message) => write!(f, "missing prices: {message}"),
+            BacktestError::Simulation(e) => write!(f, "could not simulate the plan: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BacktestError {}
+
+impl From<SqliteError> for BacktestError {
+    fn from(e: SqliteError) -> Self {
+        BacktestError::Sqlite(e)
+    }
+}
+
+impl From<SimulationError> for BacktestError {
+    fn from(e: SimulationError) -> Self {
+        BacktestError::Simulation(e)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BacktestReport {
+    pub dates: Vec<NaiveDate>,
+    pub months: Vec<SimulatedMonth>,
+    // What the holdings and the cash left over are worth at the last prices, in cents.
+    pub final_value: f64,
+    pub total_invested: i64,
+    // The yearly internal rate of return of the monthly budgets against the final value, when there is one.
+    pub irr: Option<f64>,
+    // The largest fall of the time weighted value from an earlier peak, 0.2 being 20%.
+    pub max_drawdown: f64,
+    // How far the holdings were from the ideal proportions, at worst and at the end, as the simulator measures it.
+    pub max_drift: f64,
+    pub final_drift: f64,
+}
+
+// The monthly rate at which the flows, one per month, are worth nothing today. Found by bisection, which
+// works because the budgets going out come before the value coming back. The rates are kept between -50% and
+// 100% a month, which covers any real fund and keeps the discounting of decades of flows finite.
+fn monthly_irr(flows: &[f64]) -> Option<f64> {
+    let npv = |rate: f64| flows.iter().enumerate().map(|(month, flow)| flow / (1.0 + rate).powi(month as i32)).sum::<f64>();
+    let (mut low, mut high) = (-0.5, 1.0);
+    let (npv_low, npv_high) = (npv(low), npv(high));
+    if !npv_low.is_finite() || !npv_high.is_finite() || npv_low.signum() == npv_high.signum() {
+        return None;
+    }
+    for _ in 0..200 {
+        let mid = (low + high) / 2.0;
+        if npv(mid).signum() == npv(low).signum() {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    Some((low + high) / 2.0)
+}
+
+// Leaves out the budgets, so a month only moves the index by what the prices did to the holdings.
+fn max_drawdown(months: &[SimulatedMonth]) -> f64 {
+    let mut index = 1.0;
+    let mut peak = 1.0;
+    let mut drawdown: f64 = 0.0;
+    for (previous, month) in months.iter().zip(months.iter().skip(1)) {
+        let after = previous.values.iter().sum::<f64>() + previous.cash as f64;
+        let before = previous.shares.iter().zip(&month.prices).map(|(shares, price)| shares * price).sum::<f64>() + previous.cash as f64;
+        if after > 0.0 {
+            index *= before / after;
+        }
+        peak = f64::max(peak, index);
+        drawdown = drawdown.max((peak - index) / peak);
+    }
+    drawdown
+}
+
+// Replays the plan over the prices: the budget of the settings is invested on the first day of every month in
+// whole shares, with what is left over carried into the next month. The prices have to be read for the etfs
+// of the settings, in their order.
+pub fn backtest(settings: Settings, prices: &MonthlyPrices) -> Result<BacktestReport, BacktestError> {
+    if prices.dates.len() != prices.prices.len() {
+        return Err(BacktestError::MissingPrices(format!("{} dates for {} months of prices", prices.dates.len(), prices.prices.len())));
+    }
+    let monthly_budget = settings.budget;
+    let months = Simulation::new(settings, PricePath::Supplied(prices.prices.clone()), prices.dates.len()).run()?;
+
+    let Some(last) = months.last() else {
+        return Err(BacktestError::MissingPrices("no month to invest in".to_string()));
+    };
+    let final_value = last.values.iter().sum::<f64>() + last.cash as f64;
+    let mut flows = vec![-monthly_budget as f64; months.len()];
+    flows[months.len() - 1] += final_value;
+
+    Ok(BacktestReport {
+        dates: prices.dates.clone(),
+        final_value,
+        total_invested: last.contributed,
+        irr: monthly_irr(&flows).map(|rate| (1.0 + rate).powi(12) - 1.0),
+        max_drawdown: max_drawdown(&months),
+        max_drift: months.iter().map(|month| month.tracking_error).fold(0.0, f64::max),
+        final_drift: last.tracking_error,
+        months,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use database::{Database, PriceBarData};
+    use investment_planner::EtfSetting;
+
+    fn settings() -> Settings {
+        Settings::new(10_000, vec![EtfSetting::new("IUSE.L".into(), "".to_string(), "".to_string(), 1.0, 0)])
+    }
+
+    #[test]
+    fn test_backtest_from_csv() {
+        let csv = "date,ticker,close
+2025-01-02,IUSE.L,10.0
+2025-02-03,IUSE.L,5.0
+2025-03-03,IUSE.L,10.0
+";
+        let prices = MonthlyPrices::from_csv(csv, &["IUSE.L".to_string()]).unwrap();
+        let report = backtest(settings(), &prices).unwrap();
+
+        assert_eq!(report.months.iter().map(|month| month.shares[0]).collect::<Vec<_>>(), vec![10.0, 30.0, 40.0]);
+        assert_eq!(report.final_value, 40_000.0);
+        assert_eq!(report.total_invested, 30_000);
+        assert_eq!(report.max_drawdown, 0.5);
+        assert_eq!(report.final_drift, 0.0);
+        // -100 - 100 / (1 + r) + 300 / (1 + r)^2 = 0
+        let monthly = 600.0 / (100.0 + 130_000f64.sqrt()) - 1.0;
+        assert!((report.irr.unwrap() - ((1.0 + monthly).powi(12) - 1.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_backtest_from_database() {
+        let path = std::env::temp_dir().join(format!("backtest-{}.db", std::process::id()));
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        let bar = |timestamp, close| PriceBarData::new(timestamp, close, close, close, close, close, 0);
+        // 2025-01-02, 2025-01-03 and 2025-02-03.
+        db.store_price_bars("IUSE.L", "1d", &[bar(1735776000, 10.0), bar(1735862400, 20.0), bar(1738540800, 10.0)]).unwrap();
+
+        let prices = MonthlyPrices::from_database(&db, &["IUSE.L".to_string()], &[Some("EUR".to_string())], "EUR", 0, i64::MAX).unwrap();
+        let report = backtest(settings(), &prices).unwrap();
+
+        assert_eq!(report.dates, vec![NaiveDate::from_ymd_opt(2025, 1, 2).unwrap(), NaiveDate::from_ymd_opt(2025, 2, 3).unwrap()]);
+        assert_eq!(report.final_value, 20_000.0);
+        assert_eq!(report.max_drawdown, 0.0);
+        assert!(report.irr.unwrap().abs() < 1e-9);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_backtest_rejects_mismatched_prices() {
+        let date = NaiveDate::from_ymd_opt(2025, 1, 2).unwrap();
+        let prices = |dates: Vec<NaiveDate>, prices: Vec<Vec<f64>>| MonthlyPrices { dates, prices };
+
+        assert!(matches!(backtest(settings(), &prices(vec![], vec![])), Err(BacktestError::MissingPrices(_))));
+        assert!(matches!(backtest(settings(), &prices(vec![date, date], vec![vec![10_00.0]])), Err(BacktestError::MissingPrices(_))));
+        assert!(matches!(backtest(settings(), &prices(vec![date], vec![vec![10_00.0, 5_00.0]])), Err(BacktestError::Simulation(_))));
+        assert!(matches!(backtest(settings(), &prices(vec![date], vec![vec![0.0]])), Err(BacktestError::Simulation(_))));
+    }
+
+    #[test]
+    fn test_no_irr_for_a_single_month() {
+        assert_eq!(monthly_irr(&[0.0]), None);
+    }
+
+    #[test]
+    fn test_irr_over_twenty_years() {
+        // 100 a month for 240 months, all growing by 0.5% a month until the end.
+        let months = 240;
+        let mut flows = vec![-100.0; months];
+        flows[months - 1] += (0..months).map(|month| 100.0 * 1.005f64.powi((months - 1 - month) as i32)).sum::<f64>();
+        assert!((monthly_irr(&flows).unwrap() - 0.005).abs() < 1e-9);
+
+        let lost = vec![-100.0; months];
+        assert_eq!(monthly_irr(&lost), None);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Datelike, NaiveDate};
+use database::{Database, PriceBarData};
+use yahoo_finance_info::{fx_ticker, Quote};
+
+use crate::BacktestError;
+
+// For every ticker, its earliest close in the month, if it has one.
+type FirstCloses = Vec<Option<(NaiveDate, f64)>>;
+
+// The price in cents of every etf on the first day of each month that has a price for all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonthlyPrices {
+    pub dates: Vec<NaiveDate>,
+    // One entry per date, with the prices in the order of the tickers they were read for.
+    pub prices: Vec<Vec<f64>>,
+}
+
+impl MonthlyPrices {
+    // Closing prices in the quote's major unit, per ticker and day, in any order.
+    fn from_closes(tickers: &[String], closes: Vec<(String, NaiveDate, f64)>) -> Result<MonthlyPrices, BacktestError> {
+        let mut months: BTreeMap<(i32, u32), FirstCloses> = BTreeMap::new();
+        for (ticker, date, close) in closes {
+            let Some(i) = tickers.iter().position(|t| *t == ticker) else {
+                continue;
+            };
+            let month = months.entry((date.year(), date.month())).or_insert_with(|| vec![None; tickers.len()]);
+            if month[i].is_none_or(|(first, _)| date < first) {
+                month[i] = Some((date, close));
+            }
+        }
+
+        let mut dates = vec![];
+        let mut prices = vec![];
+        for closes in months.into_values() {
+            let Some(closes) = closes.into_iter().collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            dates.push(closes.iter().map(|(date, _)| *date).min().expect("there is at least one ticker"));
+            prices.push(closes.iter().map(|(_, close)| close * 100.0 /* convert to cents */).collect());
+        }
+        if dates.is_empty() {
+            return Err(BacktestError::MissingPrices(format!("no month has a price for all of {}", tickers.join(", "))));
+        }
+        Ok(MonthlyPrices { dates, prices })
+    }
+
+    // Reads lines of `date,ticker,close` with the date as YYYY-MM-DD, after a header line.
+    pub fn from_csv(csv: &str, tickers: &[String]) -> Result<MonthlyPrices, BacktestError> {
+        let closes = csv.lines().enumerate().skip(1)
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| {
+                let parse_error = || BacktestError::Parse(format!("line {}: {line}", number + 1));
+                let [date, ticker, close] = line.split(',').map(str::trim).collect::<Vec<_>>()[..] else {
+                    return Err(parse_error());
+                };
+                let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| parse_error())?;
+                let close = close.parse::<f64>().map_err(|_| parse_error())?;
+                Ok((ticker.to_string(), date, close))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        MonthlyPrices::from_closes(tickers, closes)
+    }
+
+    // Uses the adjusted daily closes stored with the price history between start and end, in unix seconds, so
+    // that dividends count as reinvested. The closes of each ticker are in its quote currency as Yahoo reports
+    // it, e.g. GBp, and are converted into `currency` like the latest prices are: into the major unit first,
+    // then at the last daily close of the exchange rate stored under its fx ticker, up to a week before.
+    pub fn from_database(db: &Database, tickers: &[String], quote_currencies: &[Option<String>], currency: &str, start: i64, end: i64)
+        -> Result<MonthlyPrices, BacktestError> {
+        let date_of = |ticker: &str, bar: &PriceBarData| DateTime::from_timestamp(bar.timestamp, 0)
+            .map(|time| time.date_naive())
+            .ok_or_else(|| BacktestError::Parse(format!("timestamp {} of {ticker}", bar.timestamp)));
+
+        let mut closes = vec![];
+        for (ticker, quote_currency) in tickers.iter().zip(quote_currencies) {
+            let mut rates: Option<(String, Vec<PriceBarData>)> = None;
+            for bar in db.get_price_bars(ticker, "1d", start, end)? {
+                let quote = Quote::new(bar.adj_close, quote_currency.clone()).in_major_unit();
+                let close = match quote.currency.as_deref() {
+                    Some(from) if from != currency => {
+                        let (rate_ticker, rate_bars) = match &rates {
+                            Some(rates) => rates,
+                            None => {
+                                let rate_ticker = fx_ticker(from, currency);
+                                let rate_bars = db.get_price_bars(&rate_ticker, "1d", start.saturating_sub(7 * 24 * 60 * 60), end)?;
+                                rates.insert((rate_ticker, rate_bars))
+                            }
+                        };
+                        let rate = rate_bars.iter()
+                            .take_while(|rate| rate.timestamp <= bar.timestamp)
+                            .last()
+                            .filter(|rate| bar.timestamp - rate.timestamp <= 7 * 24 * 60 * 60)
+                            .ok_or_else(|| BacktestError::MissingPrices(format!("no {rate_ticker} rate for {ticker} on {}", date_of(ticker, &bar).unwrap_or_default())))?;
+                        quote.price * rate.close
+                    }
+                    _ => quote.price,
+                };
+                closes.push((ticker.clone(), date_of(ticker, &bar)?, close));
+            }
+        }
+        MonthlyPrices::from_closes(tickers, closes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tickers() -> Vec<String> {
+        vec!["IUSE.L".to_string(), "AGGG.L".to_string()]
+    }
+
+    #[test]
+    fn test_from_csv_takes_first_price_of_each_month() {
+        let csv = "date,ticker,close
+2025-01-03,IUSE.L,110.5
+2025-01-02,IUSE.L,110.0
+2025-01-02,AGGG.L,5.0
+2025-02-03,IUSE.L,112.0
+2025-02-04,AGGG.L,5.25
+2025-03-03,IUSE.L,113.0
+";
+        let prices = MonthlyPrices::from_csv(csv, &tickers()).unwrap();
+
+        assert_eq!(prices.dates, vec![NaiveDate::from_ymd_opt(2025, 1, 2).unwrap(), NaiveDate::from_ymd_opt(2025, 2, 3).unwrap()]);
+        assert_eq!(prices.prices, vec![vec![11_000.0, 500.0], vec![11_200.0, 525.0]]);
+    }
+
+    #[test]
+    fn test_from_csv_rejects_malformed_lines() {
+        let result = MonthlyPrices::from_csv("date,ticker,close\n2025-01-02,IUSE.L\n", &tickers());
+        assert!(matches!(result, Err(BacktestError::Parse(_))));
+        let result = MonthlyPrices::from_csv("date,ticker,close\n", &tickers());
+        assert!(matches!(result, Err(BacktestError::MissingPrices(_))));
+    }
+
+    #[test]
+    fn test_from_database_converts_adjusted_pence() {
+        let path = std::env::temp_dir().join(format!("backtest-prices-{}.db", std::process::id()));
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        let bar = |timestamp, close, adj_close| PriceBarData::new(timestamp, close, close, close, close, adj_close, 0);
+        // 2025-01-02 and 2025-02-03, with a dividend paid in between.
+        db.store_price_bars("IUSE.L", "1d", &[bar(1735776000, 10_000.0, 9_800.0), bar(1738540800, 11_000.0, 11_000.0)]).unwrap();
+        db.store_price_bars("AGGG.L", "1d", &[bar(1735776000, 5.0, 5.0), bar(1738540800, 5.5, 5.5)]).unwrap();
+        // 2025-01-01, the day before the first close, and 2025-02-03.
+        db.store_price_bars("GBPEUR=X", "1d", &[bar(1735689600, 1.2, 1.2), bar(1738540800, 1.25, 1.25)]).unwrap();
+
+        let currencies = vec![Some("GBp".to_string()), Some("EUR".to_string())];
+        let prices = MonthlyPrices::from_database(&db, &tickers(), &currencies, "EUR", 0, i64::MAX).unwrap();
+        assert_eq!(prices.prices, vec![vec![98.0 * 1.2 * 100.0, 500.0], vec![110.0 * 1.25 * 100.0, 550.0]]);
+
+        let result = MonthlyPrices::from_database(&db, &tickers(), &currencies, "USD", 0, i64::MAX);
+        assert!(matches!(result, Err(BacktestError::MissingPrices(_))));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+use derive_new::new;
+use sqlite::{Row, Value};
+
+use crate::{Database, SqliteError};
+
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct PriceBarData {
+    // Unix time in seconds.
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub adj_close: f64,
+    pub volume: i64,
+}
+impl PriceBarData {
+    fn from_row(row: Row) -> PriceBarData {
+        PriceBarData::new(
+            row.read("timestamp"),
+            row.read("open"),
+            row.read("high"),
+            row.read("low"),
+            row.read("close"),
+            row.read("adj_close"),
+            row.read("volume"),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct DividendData {
+    // Unix time in seconds of the ex-dividend date.
+    pub timestamp: i64,
+    pub amount: f64,
+}
+impl DividendData {
+    fn from_row(row: Row) -> DividendData {
+        DividendData::new(row.read("timestamp"), row.read("amount"))
+    }
+}
+
+impl Database {
+    // Bars already stored for the same ticker, interval and timestamp are overwritten.
+    pub fn store_price_bars(&self, ticker: &str, interval: &str, bars: &[PriceBarData]) -> Result<(), SqliteError> {
+        self.transaction(|db| {
+            let query = "
+                INSERT OR REPLACE INTO price_history (ticker, interval, timestamp, open, high, low, close, adj_close, volume)
+                VALUES (:ticker, :interval, :timestamp, :open, :high, :low, :close, :adj_close, :volume);
+            ";
+            for bar in bars {
+                let mut statement = db.connection.prepare(query)?;
+                statement.bind::<&[(_, Value)]>(&[
+                    (":ticker", ticker.into()),
+                    (":interval", interval.into()),
+                    (":timestamp", bar.timestamp.into()),
+                    (":open", bar.open.into()),
+                    (":high", bar.high.into()),
+                    (":low", bar.low.into()),
+                    (":close", bar.close.into()),
+                    (":adj_close", bar.adj_close.into()),
+                    (":volume", bar.volume.into()),
+                ])?;
+                statement.next()?;
+            }
+            Ok(())
+        })
+    }
+
+    // Start and end are unix times in seconds, both inclusive.
+    pub fn get_price_bars(&self, ticker: &str, interval: &str, start: i64, end: i64) -> Result<Vec<PriceBarData>, SqliteError> {
+        let query = "
+            SELECT timestamp, open, high, low, close, adj_close, volume FROM price_history
+            WHERE ticker = :ticker AND interval = :interval AND timestamp BETWEEN :start AND :end
+            ORDER BY timestamp
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[
+            (":ticker", ticker.into()),
+            (":interval", interval.into()),
+            (":start", start.into()),
+            (":end", end.into()),
+        ])?;
+        statement.into_iter().map(|row| row.map(PriceBarData::from_row)).collect()
+    }
+
+    pub fn store_dividends(&self, ticker: &str, dividends: &[DividendData]) -> Result<(), SqliteError> {
+        self.transaction(|db| {
+            let query = "
+                INSERT OR REPLACE INTO dividends (ticker, timestamp, amount)
+                VALUES (:ticker, :timestamp, :amount);
+            ";
+            for dividend in dividends {
+                let mut statement = db.connection.prepare(query)?;
+                statement.bind::<&[(_, Value)]>(&[
+                    (":ticker", ticker.into()),
+                    (":timestamp", dividend.timestamp.into()),
+                    (":amount", dividend.amount.into()),
+                ])?;
+                statement.next()?;
+            }
+            Ok(())
+        })
+    }
+
+    // Start and end are unix times in seconds, both inclusive.
+    pub fn get_dividends(&self, ticker: &str, start: i64, end: i64) -> Result<Vec<DividendData>, SqliteError> {
+        let query = "
+            SELECT timestamp, amount FROM dividends
+            WHERE ticker = :ticker AND timestamp BETWEEN :start AND :end
+            ORDER BY timestamp
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":ticker", ticker.into()), (":start", start.into()), (":end", end.into())])?;
+        statement.into_iter().map(|row| row.map(DividendData::from_row)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_db;
+
+    fn bar(timestamp: i64, close: f64) -> PriceBarData {
+        PriceBarData::new(timestamp, close, close, close, close, close, 1000)
+    }
+
+    #[test]
+    fn test_price_bars_by_ticker_interval_and_range() {
+        let (db, path) = temp_db("price-bars");
+        db.store_price_bars("IUSE.L", "1d", &[bar(100, 1.0), bar(200, 2.0), bar(300, 3.0)]).unwrap();
+        db.store_price_bars("IUSE.L", "1wk", &[bar(100, 10.0)]).unwrap();
+        db.store_price_bars("AGGG.L", "1d", &[bar(200, 20.0)]).unwrap();
+        // Fetching an overlapping range again replaces the stored bars.
+        db.store_price_bars("IUSE.L", "1d", &[bar(300, 3.5), bar(400, 4.0)]).unwrap();
+
+        let bars = db.get_price_bars("IUSE.L", "1d", 200, 400).unwrap();
+        assert_eq!(bars, vec![bar(200, 2.0), bar(300, 3.5), bar(400, 4.0)]);
+        assert_eq!(db.get_price_bars("IUSE.L", "1wk", 0, 1000).unwrap(), vec![bar(100, 10.0)]);
+        assert!(db.get_price_bars("IWDA.AS", "1d", 0, 1000).unwrap().is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_dividends() {
+        let (db, path) = temp_db("dividends");
+        db.store_dividends("AGGG.L", &[DividendData::new(100, 0.05), DividendData::new(300, 0.06)]).unwrap();
+        db.store_dividends("AGGG.L", &[DividendData::new(300, 0.07)]).unwrap();
+
+        assert_eq!(db.get_dividends("AGGG.L", 0, 1000).unwrap(), vec![DividendData::new(100, 0.05), DividendData::new(300, 0.07)]);
+        assert_eq!(db.get_dividends("AGGG.L", 200, 1000).unwrap().len(), 1);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
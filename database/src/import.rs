@@ -0,0 +1,343 @@
+use std::fmt::{self, Display};
+use chrono::NaiveDate;
+use derive_new::new;
+
+use crate::{Database, PurchaseData, SqliteError};
+
+#[derive(Debug)]
+pub enum ImportError {
+    Sqlite(SqliteError),
+    // The line of the file, counting the header as line 1.
+    Parse { line: usize, message: String },
+}
+
+impl Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Sqlite(e) => write!(f, "database error: {e}"),
+            ImportError::Parse { line, message } => write!(f, "could not parse line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<SqliteError> for ImportError {
+    fn from(e: SqliteError) -> Self {
+        ImportError::Sqlite(e)
+    }
+}
+
+// The most decimals a quantity can have, like the fractional shares of the planner.
+pub const MAX_QUANTITY_DECIMALS: u32 = 8;
+
+// Which columns of a broker's transaction export, by header name, hold what a purchase needs. Prices are read
+// in the major unit of the budget currency, and sells have a negative quantity.
+
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct ColumnMapping {
+    pub date: String,
+    // As understood by chrono, e.g. %Y-%m-%d.
+    pub date_format: String,
+    pub isin: String,
+    pub quantity: String,
+    pub price: String,
+    // The price column holds the value of the whole transaction, which is divided by the quantity. Its sign
+    // is ignored, since exports write either buys or sells as negative values.
+    #[new(default)]
+    pub price_is_total: bool,
+    // Without a fees column the purchases are recorded without fees.
+    #[new(default)]
+    pub fees: Option<String>,
+    #[new(value = "','")]
+    pub delimiter: char,
+    // For exports that write 1,5 rather than 1.5.
+    #[new(default)]
+    pub decimal_comma: bool,
+}
+
+impl Default for ColumnMapping {
+    fn default() -> Self {
+        ColumnMapping::new("date".into(), "%Y-%m-%d".into(), "isin".into(), "quantity".into(), "price".into())
+            .with_fees("fees".into())
+    }
+}
+
+impl ColumnMapping {
+    // The Transactions.csv export of DEGIRO in English, whose fees are negative amounts in the account currency.
+    // Its Price is in the currency of the listing, so the price is taken from the Value in the account currency.
+    pub fn degiro() -> Self {
+        ColumnMapping::new("Date".into(), "%d-%m-%Y".into(), "ISIN".into(), "Quantity".into(), "Price".into())
+            .with_total_price("Value".into())
+            .with_fees("Transaction and/or third party fees".into())
+    }
+
+    pub fn with_price(self, price: String) -> Self {
+        Self { price, price_is_total: false, ..self }
+    }
+
+    pub fn with_total_price(self, price: String) -> Self {
+        Self { price, price_is_total: true, ..self }
+    }
+
+    pub fn with_fees(self, fees: String) -> Self {
+        Self { fees: Some(fees), ..self }
+    }
+
+    pub fn without_fees(self) -> Self {
+        Self { fees: None, ..self }
+    }
+
+    pub fn with_delimiter(self, delimiter: char) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    pub fn with_decimal_comma(self, decimal_comma: bool) -> Self {
+        Self { decimal_comma, ..self }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct ImportedTransaction {
+    pub line: usize,
+    pub isin: String,
+    // As YYYY-MM-DD, like the dates of the purchases.
+    pub date: String,
+    // In units of 10^-quantity_decimals shares, negative for sells.
+    pub quantity: i64,
+    pub quantity_decimals: u32,
+    pub unit_price: i64,
+    pub fees: i64,
+}
+
+// What an import records, or would record on a dry run.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ImportDiff {
+    // The purchases with their etf, with id 0 on a dry run.
+    pub added: Vec<PurchaseData>,
+    // Transactions the ledger already has, so importing the same export twice records them once.
+    pub duplicates: Vec<ImportedTransaction>,
+    // Transactions in isins no configured etf has.
+    pub unmatched: Vec<ImportedTransaction>,
+    // The cumulative amount of every etf that changes, before and after.
+    pub cumulative_changes: Vec<(String, i64, i64)>,
+}
+
+// Splits a line on the delimiter outside of double quotes, where "" is a quote.
+fn split_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().expect("there is a field").push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => fields.push(String::new()),
+            c => fields.last_mut().expect("there is a field").push(c),
+        }
+    }
+    fields.into_iter().map(|field| field.trim().to_string()).collect()
+}
+
+// Reads a decimal number exactly, as the digits and how many of them follow the decimal separator.
+fn parse_decimal(value: &str, decimal_comma: bool) -> Option<(i64, u32)> {
+    let (separator, grouping) = if decimal_comma { (',', '.') } else { ('.', ',') };
+    let value = value.replace(grouping, "");
+    let (whole, fraction) = value.split_once(separator).unwrap_or((&value, ""));
+    let fraction = fraction.trim_end_matches('0');
+    if (whole.trim_start_matches(['-', '+']).is_empty() && fraction.is_empty()) || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let digits = format!("{whole}{fraction}").parse::<i64>().ok()?;
+    Some((digits, fraction.len() as u32))
+}
+
+// Amounts above this many cents, ten trillion in the major unit, are taken to be garbage rather than a transaction.
+const MAX_CENTS: i64 = 1_000_000_000_000_000;
+
+// Rounded to the nearest cent, or None above MAX_CENTS.
+fn to_cents((digits, decimals): (i64, u32)) -> Option<i64> {
+    let digits = digits as i128;
+    let cents = if decimals <= 2 {
+        digits * 10i128.pow(2 - decimals)
+    } else {
+        // Below a cent whenever the scale is beyond an i128.
+        let scale = 10i128.checked_pow(decimals - 2).unwrap_or(i128::MAX);
+        (digits + scale / 2 * digits.signum()) / scale
+    };
+    i64::try_from(cents).ok().filter(|cents| cents.abs() <= MAX_CENTS)
+}
+
+// Reads the transactions of an export with a header line, skipping empty lines.
+pub fn parse_transactions(csv: &str, mapping: &ColumnMapping) -> Result<Vec<ImportedTransaction>, ImportError> {
+    let mut lines = csv.trim_start_matches('\u{feff}').lines().enumerate().map(|(i, line)| (i + 1, line));
+    let Some((_, header)) = lines.next() else {
+        return Ok(vec![]);
+    };
+    let header = split_line(header, mapping.delimiter);
+    let column = |name: &str| header.iter().position(|h| h == name)
+        .ok_or_else(|| ImportError::Parse { line: 1, message: format!("there is no column {name}") });
+    let (date, isin, quantity, price) = (column(&mapping.date)?, column(&mapping.isin)?, column(&mapping.quantity)?, column(&mapping.price)?);
+    let fees = mapping.fees.as_deref().map(column).transpose()?;
+
+    lines.filter(|(_, line)| !line.trim().is_empty()).map(|(line, text)| {
+        let fields = split_line(text, mapping.delimiter);
+        let parse_error = |message: String| ImportError::Parse { line, message };
+        let field = |i: usize| fields.get(i).map(String::as_str).ok_or_else(|| parse_error(format!("it has only {} fields", fields.len())));
+        let decimal = |i: usize| field(i).and_then(|value| parse_decimal(value, mapping.decimal_comma)
+            .ok_or_else(|| parse_error(format!("{value} is not a number"))));
+        let cents = |i: usize| decimal(i).and_then(|value| to_cents(value)
+            .ok_or_else(|| parse_error(format!("{} is not an amount of at most {MAX_CENTS} cents", field(i).unwrap_or_default()))));
+
+        let date = NaiveDate::parse_from_str(field(date)?, &mapping.date_format)
+            .map_err(|e| parse_error(format!("the date {}: {e}", field(date).unwrap_or_default())))?;
+        let (quantity, quantity_decimals) = decimal(quantity)?;
+        if quantity_decimals > MAX_QUANTITY_DECIMALS {
+            return Err(parse_error(format!("quantities have at most {MAX_QUANTITY_DECIMALS} decimals")));
+        }
+        let unit_price = if mapping.price_is_total && quantity != 0 {
+            // Rounded to the nearest cent, in i128 since the value is scaled up by the decimals of the quantity.
+            let value = cents(price)?.abs() as i128 * 10i128.pow(quantity_decimals);
+            let quantity = quantity.abs() as i128;
+            i64::try_from((2 * value + quantity) / (2 * quantity)).ok().filter(|&price| price <= MAX_CENTS)
+                .ok_or_else(|| parse_error(format!("the price per share is more than {MAX_CENTS} cents")))?
+        } else {
+            cents(price)?
+        };
+        let fees = match fees {
+            Some(i) if !field(i)?.is_empty() => cents(i)?.abs(),
+            _ => 0,
+        };
+        if quantity == 0 || unit_price < 0 {
+            return Err(parse_error("a transaction needs a quantity and a price that is not negative".to_string()));
+        }
+        Ok(ImportedTransaction::new(line, field(isin)?.to_string(), date.format("%Y-%m-%d").to_string(), quantity, quantity_decimals, unit_price, fees))
+    }).collect()
+}
+
+impl Database {
+    // Records the transactions as purchases of the configured etf with their isin, which adds them to its
+    // cumulative amount. A dry run only works out what would change.
+    pub fn import_transactions(&self, transactions: &[ImportedTransaction], dry_run: bool) -> Result<ImportDiff, SqliteError> {
+        self.transaction(|db| {
+            let etfs = db.get_all_etfs()?.collect::<Result<Vec<_>, _>>()?;
+            let mut ledger = db.list_purchases()?;
+            let mut diff = ImportDiff::default();
+
+            for transaction in transactions {
+                let Some(etf) = etfs.iter().find(|etf| etf.isin == transaction.isin) else {
+                    diff.unmatched.push(transaction.clone());
+                    continue;
+                };
+                let purchase = PurchaseData {
+                    quantity_decimals: transaction.quantity_decimals,
+                    ..PurchaseData::new(0, etf.id.clone(), transaction.quantity, transaction.unit_price, transaction.fees, transaction.date.clone())
+                };
+                // Each purchase in the ledger stands for at most one imported transaction.
+                let same = |recorded: &PurchaseData| recorded.etf_id == purchase.etf_id && recorded.date == purchase.date
+                    && recorded.units() == purchase.units() && recorded.unit_price == purchase.unit_price;
+                if let Some(i) = ledger.iter().position(same) {
+                    ledger.swap_remove(i);
+                    diff.duplicates.push(transaction.clone());
+                    continue;
+                }
+
+                let id = if dry_run {
+                    0
+                } else {
+                    db.record_fractional_purchase(&purchase.etf_id, purchase.quantity, purchase.quantity_decimals, purchase.unit_price, purchase.fees, &purchase.date)?
+                };
+                diff.added.push(PurchaseData { id, ..purchase });
+            }
+
+            for etf in &etfs {
+                let added = diff.added.iter().filter(|purchase| purchase.etf_id == etf.id).map(PurchaseData::amount).sum::<i64>();
+                if added != 0 {
+                    diff.cumulative_changes.push((etf.id.clone(), etf.cumulative, etf.cumulative + added));
+                }
+            }
+            Ok(diff)
+        })
+    }
+}
+
+// Amounts are written as cents, e.g. 112_36.
+#[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_db;
+    use crate::EtfData;
+
+    #[test]
+    fn test_parse_degiro_export() {
+        let csv = "\u{feff}Date,Time,Product,ISIN,Reference exchange,Venue,Quantity,Price,,Local value,,Value,,Exchange rate,Transaction and/or third party fees,,Total,,Order ID
+03-02-2026,09:04,\"ISHARES CORE S&P 500 UCITS ETF, ACC\",IE00B3ZW0K18,LSE,XLON,3,112.3600,GBP,-337.08,GBP,-393.42,EUR,1.1671,-2.00,EUR,-395.42,EUR,abc
+04-02-2026,10:15,ISHARES CORE GLOBAL AGGREGATE BOND,IE00B3F81409,LSE,XLON,-10,4.875,GBP,48.75,GBP,56.89,EUR,1.1670,,EUR,56.89,EUR,def
+";
+        let transactions = parse_transactions(csv, &ColumnMapping::degiro()).unwrap();
+
+        assert_eq!(transactions, vec![
+            ImportedTransaction::new(2, "IE00B3ZW0K18".into(), "2026-02-03".into(), 3, 0, 131_14, 2_00),
+            ImportedTransaction::new(3, "IE00B3F81409".into(), "2026-02-04".into(), -10, 0, 5_69, 0),
+        ]);
+    }
+
+    #[test]
+    fn test_parse_total_price() {
+        let csv = "isin,date,quantity,value\nIE00B3ZW0K18,2026-02-03,0.125,-14.05\n";
+        let mapping = ColumnMapping::default().without_fees().with_total_price("value".into());
+        let transactions = parse_transactions(csv, &mapping).unwrap();
+        assert_eq!(transactions, vec![ImportedTransaction::new(2, "IE00B3ZW0K18".into(), "2026-02-03".into(), 125, 3, 112_40, 0)]);
+    }
+
+    #[test]
+    fn test_parse_generic_export() {
+        let csv = "isin;date;quantity;price\nIE00B3ZW0K18;2026-02-03;0,125;1.112,36\n\n";
+        let mapping = ColumnMapping::default().without_fees().with_delimiter(';').with_decimal_comma(true);
+        let transactions = parse_transactions(csv, &mapping).unwrap();
+        assert_eq!(transactions, vec![ImportedTransaction::new(2, "IE00B3ZW0K18".into(), "2026-02-03".into(), 125, 3, 1_112_36, 0)]);
+
+        let result = parse_transactions("isin;date;quantity;price\nIE00B3ZW0K18;03-02-2026;1;1\n", &mapping);
+        assert!(matches!(result, Err(ImportError::Parse { line: 2, .. })));
+        let result = parse_transactions("isin,date,quantity\n", &ColumnMapping::default());
+        assert!(matches!(result, Err(ImportError::Parse { line: 1, .. })));
+        let result = parse_transactions("isin;date;quantity;price\nIE00B3ZW0K18;2026-02-03;0,000000001;1\n", &mapping);
+        assert!(matches!(result, Err(ImportError::Parse { line: 2, .. })));
+        let price_of = |price: &str| parse_transactions(&format!("isin;date;quantity;price\nIE00B3ZW0K18;2026-02-03;1;{price}\n"), &mapping)
+            .map(|transactions| transactions[0].unit_price);
+        assert!(matches!(price_of("92233720368547758,07"), Err(ImportError::Parse { line: 2, .. })));
+        assert!(matches!(price_of("100.000.000.000.000"), Err(ImportError::Parse { line: 2, .. })));
+        assert_eq!(price_of("0,00000000000000000000001").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_import_transactions() {
+        let (db, path) = temp_db("import-transactions");
+        db.add_etf(EtfData::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "NAME".into(), 1.0, 100_00)).unwrap();
+        db.record_purchase("IUSE.L", 3, 10_00, 1_00, "2026-01-05").unwrap();
+        let transactions = vec![
+            ImportedTransaction::new(2, "IE00B3ZW0K18".into(), "2026-01-05".into(), 3, 0, 10_00, 1_00),
+            ImportedTransaction::new(3, "IE00B3ZW0K18".into(), "2026-02-05".into(), 2_500, 3, 12_00, 1_00),
+            ImportedTransaction::new(4, "IE00B4L5Y983".into(), "2026-02-05".into(), 1, 0, 98_00, 0),
+        ];
+
+        let dry_run = db.import_transactions(&transactions, true).unwrap();
+        assert_eq!(dry_run.added.len(), 1);
+        assert_eq!(dry_run.duplicates.iter().map(|t| t.line).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(dry_run.unmatched.iter().map(|t| t.line).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(dry_run.cumulative_changes, vec![("IUSE.L".to_string(), 130_00, 160_00)]);
+        assert_eq!(db.list_purchases().unwrap().len(), 1);
+
+        let diff = db.import_transactions(&transactions, false).unwrap();
+        assert_eq!(diff.cumulative_changes, dry_run.cumulative_changes);
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 160_00);
+        assert_eq!(db.get_purchase(diff.added[0].id).unwrap().unwrap(), diff.added[0]);
+        assert!(db.import_transactions(&transactions, false).unwrap().added.is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,47 +1,199 @@
+mod history;
+mod import;
+mod migrations;
+mod plan;
+mod prices;
+mod purchases;
+
+use std::collections::HashMap;
 use derive_new::new;
+use serde::{Deserialize, Serialize};
 pub use sqlite::Error as SqliteError;
-use sqlite::{Connection, Value};
+use sqlite::{Connection, OpenFlags, Row, Value};
+
+pub use history::{DividendData, PriceBarData};
+pub use import::{parse_transactions, ColumnMapping, ImportDiff, ImportError, ImportedTransaction, MAX_QUANTITY_DECIMALS};
+pub use migrations::SCHEMA_VERSION;
+pub use plan::{PlanDocument, PlanError, PlanFormat, PLAN_VERSION};
+pub use prices::PriceData;
+pub use purchases::PurchaseData;
 
 pub struct Database {
     connection: Connection
 }
 
-#[derive(Debug, new)]
+#[derive(Debug, Clone, Copy, new)]
+pub struct DatabaseOptions {
+    pub create_if_missing: bool,
+    pub busy_timeout_ms: usize,
+}
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions::new(true, 0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
 pub struct EtfData {
     pub id: String,
     pub isin: String,
     pub name: String,
     pub proportion: f64,
+    // The amount invested so far: the etf table stores what was invested before the purchase ledger,
+    // and the purchases of the etf are added to it when reading.
     pub cumulative: i64,
+    // The broker's fee per order on this etf: a fixed amount in cents plus hundredths of a percent.
+    #[new(default)]
+    pub fee_fixed: i64,
+    #[new(default)]
+    pub fee_basis_points: i64,
+    // Limits on a single order: no maximum when max_quantity is None, and the amount in cents.
+    #[new(default)]
+    pub min_quantity: i64,
+    #[new(default)]
+    pub max_quantity: Option<i64>,
+    #[new(value = "1")]
+    pub lot_size: i64,
+    #[new(default)]
+    pub min_order_amount: i64,
+}
+impl EtfData {
+    pub fn with_fee(self, fee_fixed: i64, fee_basis_points: i64) -> Self {
+        Self { fee_fixed, fee_basis_points, ..self }
+    }
+
+    pub fn with_limits(self, min_quantity: i64, max_quantity: Option<i64>, lot_size: i64, min_order_amount: i64) -> Self {
+        Self { min_quantity, max_quantity, lot_size, min_order_amount, ..self }
+    }
+
+    fn with_invested(self, invested: &HashMap<String, i64>) -> Self {
+        let cumulative = self.cumulative + invested.get(&self.id).copied().unwrap_or(0);
+        Self { cumulative, ..self }
+    }
+
+    fn from_row(row: Row) -> EtfData {
+        let id: &str = row.read("id");
+        let isin: &str = row.read("isin");
+        let name: &str = row.read("name");
+        let proportion: f64 = row.read("proportion");
+        let cumulative: i64 = row.read("cumulative");
+        let fee_fixed: i64 = row.read("fee_fixed");
+        let fee_basis_points: i64 = row.read("fee_basis_points");
+        let min_quantity: i64 = row.read("min_quantity");
+        let max_quantity: Option<i64> = row.read("max_quantity");
+        let lot_size: i64 = row.read("lot_size");
+        let min_order_amount: i64 = row.read("min_order_amount");
+
+        EtfData::new(id.to_string(), isin.to_string(), name.to_string(), proportion, cumulative)
+            .with_fee(fee_fixed, fee_basis_points)
+            .with_limits(min_quantity, max_quantity, lot_size, min_order_amount)
+    }
+}
+
+// Selling to get back to the ideal proportions, which is off unless stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, new)]
+pub struct RebalancingData {
+    pub max_turnover_basis_points: i64,
+    pub sell_fee_fixed: i64,
+    pub sell_fee_basis_points: i64,
+}
+
+/// An open `SAVEPOINT tx`. If it is dropped without being finished, i.e. when the
+/// transaction body panics, the savepoint is rolled back and released so the
+/// connection is not left inside a half-applied transaction.
+struct Savepoint<'a> {
+    connection: &'a Connection,
+    open: bool,
+}
+
+impl Savepoint<'_> {
+    // Stays open when the statement fails, e.g. a RELEASE on a busy database, so that dropping it rolls back.
+    fn finish(mut self, statement: &str) -> Result<(), SqliteError> {
+        self.connection.execute(statement)?;
+        self.open = false;
+        Ok(())
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if self.open {
+            let _ = self.connection.execute("ROLLBACK TO tx; RELEASE tx;");
+        }
+    }
 }
 
 impl Database {
     pub fn new(file_path: &str) -> Result<Database, SqliteError> {
-        let connection = sqlite::open(file_path)?;
+        Database::open(file_path, DatabaseOptions::default())
+    }
 
-        let query = "
-            CREATE TABLE IF NOT EXISTS etf (id TEXT PRIMARY KEY, isin TEXT, name TEXT, proportion FLOAT, cumulative INTEGER);
-            CREATE TABLE IF NOT EXISTS budget (id INTEGER PRIMARY KEY, budget INTEGER);
-        ";
-        connection.execute(query)?;
-
-        {
-            let query = "
-                INSERT OR IGNORE INTO budget (id, budget)
-                values (0, 0);
-            ";
-            let mut statement = connection.prepare(query)?;
-            statement.next()?;
+    pub fn open(file_path: &str, options: DatabaseOptions) -> Result<Database, SqliteError> {
+        let mut flags = OpenFlags::new().with_read_write();
+        if options.create_if_missing {
+            flags = flags.with_create();
+        }
+        let mut connection = Connection::open_with_flags(file_path, flags)?;
+        if options.busy_timeout_ms > 0 {
+            connection.set_busy_timeout(options.busy_timeout_ms)?;
         }
 
+        migrations::migrate(&connection)?;
+
         let db = Database { connection };
         Ok(db)
     }
 
+    pub fn transaction<T, E: From<SqliteError>>(&self, f: impl FnOnce(&Database) -> Result<T, E>) -> Result<T, E> {
+        // Savepoints rather than BEGIN, so that transactions can be nested.
+        self.connection.execute("SAVEPOINT tx;")?;
+        let savepoint = Savepoint { connection: &self.connection, open: true };
+        match f(self) {
+            Ok(t) => {
+                savepoint.finish("RELEASE tx;")?;
+                Ok(t)
+            }
+            Err(e) => {
+                savepoint.finish("ROLLBACK TO tx; RELEASE tx;")?;
+                Err(e)
+            }
+        }
+    }
+
+    // Replaces the budget and etfs. The etfs that remain keep their row, so that add_etf can keep the
+    // cumulative amount of those with purchases.
+    pub fn replace_settings(&self, budget: i64, etfs: Vec<EtfData>) -> Result<(), SqliteError> {
+        self.transaction(|db| {
+            db.set_budget(budget)?;
+            let removed = db.get_all_etfs()?
+                .map(|etf| etf.map(|etf| etf.id))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .filter(|id| !etfs.iter().any(|etf| &etf.id == id));
+            for id in removed {
+                db.remove_etf(id)?;
+            }
+            for etf in etfs {
+                db.add_etf(etf)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Once the etf has purchases its cumulative amount comes from the ledger, and the one given is ignored
+    // so that settings read before a purchase was recorded cannot overwrite it; see update_cumulative. Callers
+    // that take settings from users reject such a conflicting amount instead.
     pub fn add_etf(&self, etf: EtfData) -> Result<(), SqliteError> {
         let query = "
-            INSERT OR REPLACE INTO etf (id, isin, name, proportion, cumulative) 
-            VALUES (:id, :isin, :name, :proportion, :cumulative);
+            INSERT OR REPLACE INTO etf (id, isin, name, proportion, cumulative, fee_fixed, fee_basis_points, min_quantity, max_quantity, lot_size, min_order_amount)
+            VALUES (
+                :id, :isin, :name, :proportion,
+                CASE WHEN EXISTS (SELECT 1 FROM purchases WHERE etf_id = :id)
+                    THEN COALESCE((SELECT cumulative FROM etf WHERE id = :id), 0)
+                    ELSE :cumulative
+                END,
+                :fee_fixed, :fee_basis_points, :min_quantity, :max_quantity, :lot_size, :min_order_amount
+            );
         ";
         let mut statement = self.connection.prepare(query)?;
         statement.bind::<&[(_, Value)]>(&[
@@ -50,53 +202,51 @@ impl Database {
             (":name", etf.name.into()),
             (":proportion", etf.proportion.into()),
             (":cumulative", etf.cumulative.into()),
+            (":fee_fixed", etf.fee_fixed.into()),
+            (":fee_basis_points", etf.fee_basis_points.into()),
+            (":min_quantity", etf.min_quantity.into()),
+            (":max_quantity", etf.max_quantity.map_or(Value::Null, Value::from)),
+            (":lot_size", etf.lot_size.into()),
+            (":min_order_amount", etf.min_order_amount.into()),
         ])?;
         statement.next()?;
         Ok(())
     }
 
+    // Removes the purchases of the etf with it, so adding it again later does not bring them back.
     pub fn remove_etf(&self, etf_id: String) -> Result<(), SqliteError> {
-        let query = "
-            DELETE FROM etf WHERE id = :id;
-        ";
-        let mut statement = self.connection.prepare(query)?;
-        statement.bind::<&[(_, Value)]>(&[
-            (":id", etf_id.into()),
-        ])?;
-        statement.next()?;
-        Ok(())
+        self.transaction(|db| {
+            for query in ["DELETE FROM purchases WHERE etf_id = :id;", "DELETE FROM etf WHERE id = :id;"] {
+                let mut statement = db.connection.prepare(query)?;
+                statement.bind::<&[(_, Value)]>(&[
+                    (":id", etf_id.as_str().into()),
+                ])?;
+                statement.next()?;
+            }
+            Ok(())
+        })
     }
     
+    pub fn remove_all_etfs(&self) -> Result<(), SqliteError> {
+        self.transaction(|db| db.connection.execute("DELETE FROM purchases; DELETE FROM etf;"))
+    }
+
     pub fn get_all_etfs(&self) -> Result<impl Iterator<Item = Result<EtfData, SqliteError>> + use<'_>, SqliteError> {
-        let query = "SELECT id, isin, name, proportion, cumulative FROM etf";
+        let query = "SELECT * FROM etf";
     
         let statement = self.connection.prepare(query)?;
+        let invested = self.invested_amounts()?;
     
-        Ok(statement.into_iter().map(|row| row.map(|row| {
-            let id: &str = row.read("id");
-            let isin: &str = row.read("isin");
-            let name: &str = row.read("name");
-            let proportion: f64 = row.read("proportion");
-            let cumulative: i64 = row.read("cumulative");
-            
-            EtfData::new(id.to_string(), isin.to_string(), name.to_string(), proportion, cumulative)
-        })))
+        Ok(statement.into_iter().map(move |row| row.map(|row| EtfData::from_row(row).with_invested(&invested))))
     }
 
     pub fn get_etf(&self, etf_id: &str) -> Result<Option<EtfData>, SqliteError> {
-        let query = "SELECT id, isin, name, proportion, cumulative FROM etf WHERE id = :id";
+        let query = "SELECT * FROM etf WHERE id = :id";
         let mut statement = self.connection.prepare(query)?;
         statement.bind::<&[(_, Value)]>(&[(":id", etf_id.into())])?;
+        let invested = self.invested_amounts()?;
 
-        statement.into_iter().map(|row| row.map(|row| {
-            let id: &str = row.read("id");
-            let isin: &str = row.read("isin");
-            let name: &str = row.read("name");
-            let proportion: f64 = row.read("proportion");
-            let cumulative: i64 = row.read("cumulative");
-            
-            EtfData::new(id.to_string(), isin.to_string(), name.to_string(), proportion, cumulative)
-        })).next().transpose()
+        statement.into_iter().map(|row| row.map(|row| EtfData::from_row(row).with_invested(&invested))).next().transpose()
     }
     
     pub fn update_proportion(&self, etf_id: &str, proportion: f64) -> Result<(), SqliteError>{
@@ -111,22 +261,24 @@ impl Database {
         Ok(())
     }
 
+    // Sets the cumulative amount including the purchases, e.g. to correct it after a purchase outside the ledger.
     pub fn update_cumulative(&self, etf_id: &str, amount: i64) -> Result<(), SqliteError>{
         let query = "
             UPDATE etf
             SET cumulative = :amount
             WHERE id = :id;
         ";
+        let invested = self.invested_amounts()?.get(etf_id).copied().unwrap_or(0);
         let mut statement = self.connection.prepare(query)?;
-        statement.bind::<&[(_, Value)]>(&[(":amount", amount.into()), (":id", etf_id.into())])?;
+        statement.bind::<&[(_, Value)]>(&[(":amount", (amount - invested).into()), (":id", etf_id.into())])?;
         statement.next()?;
         Ok(())    
     }
 
     pub fn set_budget(&self, budget: i64) -> Result<(), SqliteError> {
         let query = "
-            INSERT OR REPLACE INTO budget (id, budget)
-            values (0, :budget);
+            INSERT INTO budget (id, budget) VALUES (0, :budget)
+            ON CONFLICT (id) DO UPDATE SET budget = excluded.budget;
         ";
         let mut statement = self.connection.prepare(query)?;
         statement.bind::<&[(_, Value)]>(&[(":budget", budget.into())])?;
@@ -144,6 +296,108 @@ impl Database {
             budget
         })).next().transpose()
     }
+
+    // The currency the budget, cumulative amounts and purchases are in; EUR unless set otherwise.
+    pub fn set_currency(&self, currency: &str) -> Result<(), SqliteError> {
+        let query = "
+            INSERT INTO budget (id, budget, currency) VALUES (0, 0, :currency)
+            ON CONFLICT (id) DO UPDATE SET currency = excluded.currency;
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":currency", currency.into())])?;
+        statement.next()?;
+        Ok(())
+    }
+
+    pub fn get_currency(&self) -> Result<Option<String>, SqliteError> {
+        let query = "
+            SELECT currency from budget WHERE id = 0;
+        ";
+        let statement = self.connection.prepare(query)?;
+        statement.into_iter().map(|row| row.map(|row| {
+            let currency: &str = row.read("currency");
+            currency.to_string()
+        })).next().transpose()
+    }
+
+    // How many decimals of a share the broker lets us buy, or None for whole shares only.
+    pub fn set_fractional_decimals(&self, decimals: Option<i64>) -> Result<(), SqliteError> {
+        let query = "
+            INSERT INTO budget (id, budget, fractional_decimals) VALUES (0, 0, :decimals)
+            ON CONFLICT (id) DO UPDATE SET fractional_decimals = excluded.fractional_decimals;
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":decimals", decimals.map_or(Value::Null, Value::from))])?;
+        statement.next()?;
+        Ok(())
+    }
+
+    pub fn get_fractional_decimals(&self) -> Result<Option<i64>, SqliteError> {
+        let query = "
+            SELECT fractional_decimals from budget WHERE id = 0;
+        ";
+        let statement = self.connection.prepare(query)?;
+        statement.into_iter().map(|row| row.map(|row| {
+            let decimals: Option<i64> = row.read("fractional_decimals");
+            decimals
+        })).next().transpose().map(Option::flatten)
+    }
+
+    pub fn set_rebalancing(&self, rebalancing: Option<RebalancingData>) -> Result<(), SqliteError> {
+        let query = "
+            INSERT INTO budget (id, budget, max_turnover_basis_points, sell_fee_fixed, sell_fee_basis_points)
+            VALUES (0, 0, :max_turnover_basis_points, :sell_fee_fixed, :sell_fee_basis_points)
+            ON CONFLICT (id) DO UPDATE SET
+                max_turnover_basis_points = excluded.max_turnover_basis_points,
+                sell_fee_fixed = excluded.sell_fee_fixed,
+                sell_fee_basis_points = excluded.sell_fee_basis_points;
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[
+            (":max_turnover_basis_points", rebalancing.map_or(Value::Null, |r| r.max_turnover_basis_points.into())),
+            (":sell_fee_fixed", rebalancing.map_or(0, |r| r.sell_fee_fixed).into()),
+            (":sell_fee_basis_points", rebalancing.map_or(0, |r| r.sell_fee_basis_points).into()),
+        ])?;
+        statement.next()?;
+        Ok(())
+    }
+
+    pub fn get_rebalancing(&self) -> Result<Option<RebalancingData>, SqliteError> {
+        let query = "
+            SELECT max_turnover_basis_points, sell_fee_fixed, sell_fee_basis_points from budget WHERE id = 0;
+        ";
+        let statement = self.connection.prepare(query)?;
+        statement.into_iter().map(|row| row.map(|row| {
+            let max_turnover_basis_points: Option<i64> = row.read("max_turnover_basis_points");
+            let sell_fee_fixed: i64 = row.read("sell_fee_fixed");
+            let sell_fee_basis_points: i64 = row.read("sell_fee_basis_points");
+            max_turnover_basis_points.map(|max_turnover_basis_points| RebalancingData::new(max_turnover_basis_points, sell_fee_fixed, sell_fee_basis_points))
+        })).next().transpose().map(Option::flatten)
+    }
+
+    // Whether the targets are computed against what the holdings are worth now instead of what was put in.
+    pub fn set_target_market_value(&self, target_market_value: bool) -> Result<(), SqliteError> {
+        let query = "
+            INSERT INTO budget (id, budget, target_market_value) VALUES (0, 0, :target_market_value)
+            ON CONFLICT (id) DO UPDATE SET target_market_value = excluded.target_market_value;
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":target_market_value", (target_market_value as i64).into())])?;
+        statement.next()?;
+        Ok(())
+    }
+
+    pub fn get_target_market_value(&self) -> Result<bool, SqliteError> {
+        let query = "
+            SELECT target_market_value from budget WHERE id = 0;
+        ";
+        let statement = self.connection.prepare(query)?;
+        let target_market_value = statement.into_iter().map(|row| row.map(|row| {
+            let target_market_value: i64 = row.read("target_market_value");
+            target_market_value != 0
+        })).next().transpose()?;
+        Ok(target_market_value.unwrap_or(false))
+    }
 }
 
 
@@ -151,6 +405,22 @@ impl Database {
 mod tests {
     use super::*;
 
+    pub(crate) fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("etf-investment-plan-{name}-db"));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    pub(crate) fn temp_db(name: &str) -> (Database, std::path::PathBuf) {
+        let path = temp_path(name);
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        (db, path)
+    }
+
+    fn etf_ids(db: &Database) -> Vec<String> {
+        db.get_all_etfs().unwrap().map(|etf| etf.unwrap().id).collect()
+    }
+
     #[test]
     fn test_connect() {
         let db = Database::new("db").unwrap();
@@ -193,10 +463,209 @@ mod tests {
         db.set_budget(42).unwrap();
     }
 
+    #[test]
+    fn test_open_missing_file_without_create() {
+        let path = std::env::temp_dir().join("etf-investment-plan-missing-db");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::open(path.to_str().unwrap(), DatabaseOptions::new(false, 0));
+        assert!(db.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_open_creates_file() {
+        let path = std::env::temp_dir().join("etf-investment-plan-created-db");
+        let _ = std::fs::remove_file(&path);
+        let db = Database::open(path.to_str().unwrap(), DatabaseOptions::default()).unwrap();
+        assert_eq!(db.get_budget().unwrap(), Some(0));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn test_get_budget() {
         let db = Database::new("db").unwrap();
         let b = db.get_budget().unwrap().unwrap();
         println!("{b}")
     }
+
+    #[test]
+    fn test_etf_fee_round_trip() {
+        let (db, path) = temp_db("etf-fee");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN1".into(), "NAME 1".into(), 0.5, 0).with_fee(2_00, 25)).unwrap();
+        db.add_etf(EtfData::new("AGGG.L".into(), "ISIN2".into(), "NAME 2".into(), 0.5, 0)).unwrap();
+
+        let etf = db.get_etf("IUSE.L").unwrap().unwrap();
+        assert_eq!((etf.fee_fixed, etf.fee_basis_points), (2_00, 25));
+        let etf = db.get_etf("AGGG.L").unwrap().unwrap();
+        assert_eq!((etf.fee_fixed, etf.fee_basis_points), (0, 0));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_etf_limits_round_trip() {
+        let (db, path) = temp_db("etf-limits");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN1".into(), "NAME 1".into(), 0.5, 0).with_limits(2, Some(10), 2, 50_00)).unwrap();
+        db.add_etf(EtfData::new("AGGG.L".into(), "ISIN2".into(), "NAME 2".into(), 0.5, 0)).unwrap();
+
+        let etf = db.get_etf("IUSE.L").unwrap().unwrap();
+        assert_eq!((etf.min_quantity, etf.max_quantity, etf.lot_size, etf.min_order_amount), (2, Some(10), 2, 50_00));
+        let etf = db.get_etf("AGGG.L").unwrap().unwrap();
+        assert_eq!((etf.min_quantity, etf.max_quantity, etf.lot_size, etf.min_order_amount), (0, None, 1, 0));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_fractional_decimals_round_trip() {
+        let (db, path) = temp_db("fractional-decimals");
+        assert_eq!(db.get_fractional_decimals().unwrap(), None);
+        db.set_fractional_decimals(Some(3)).unwrap();
+        db.set_budget(500).unwrap();
+        assert_eq!(db.get_fractional_decimals().unwrap(), Some(3));
+        db.set_fractional_decimals(None).unwrap();
+        assert_eq!(db.get_fractional_decimals().unwrap(), None);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rebalancing_round_trip() {
+        let (db, path) = temp_db("rebalancing");
+        assert_eq!(db.get_rebalancing().unwrap(), None);
+        let rebalancing = RebalancingData::new(5_00, 1_00, 10);
+        db.set_rebalancing(Some(rebalancing)).unwrap();
+        db.set_budget(500).unwrap();
+        assert_eq!(db.get_rebalancing().unwrap(), Some(rebalancing));
+        db.set_rebalancing(None).unwrap();
+        assert_eq!(db.get_rebalancing().unwrap(), None);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_target_market_value_round_trip() {
+        let (db, path) = temp_db("target-market-value");
+        assert!(!db.get_target_market_value().unwrap());
+        db.set_target_market_value(true).unwrap();
+        db.set_budget(500).unwrap();
+        assert!(db.get_target_market_value().unwrap());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_currency_defaults_to_euro() {
+        let (db, path) = temp_db("currency");
+        assert_eq!(db.get_currency().unwrap(), Some("EUR".to_string()));
+        db.set_currency("GBP").unwrap();
+        db.set_budget(500).unwrap();
+        assert_eq!(db.get_currency().unwrap(), Some("GBP".to_string()));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_replace_settings() {
+        let (db, path) = temp_db("replace-settings");
+        db.add_etf(EtfData::new("AGGG.L".into(), "ISIN1".into(), "NAME 1".into(), 0.5, 100)).unwrap();
+
+        db.replace_settings(300, vec![
+            EtfData::new("IUSE.L".into(), "ISIN2".into(), "NAME 2".into(), 0.3, 10),
+            EtfData::new("IWDA.AS".into(), "ISIN3".into(), "NAME 3".into(), 0.7, 20),
+        ]).unwrap();
+
+        assert_eq!(db.get_budget().unwrap(), Some(300));
+        assert_eq!(etf_ids(&db), vec!["IUSE.L".to_string(), "IWDA.AS".to_string()]);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_after_failure() {
+        let (db, path) = temp_db("transaction-rollback");
+        db.set_budget(100).unwrap();
+        db.add_etf(EtfData::new("AGGG.L".into(), "ISIN1".into(), "NAME 1".into(), 0.5, 100)).unwrap();
+
+        let result: Result<(), SqliteError> = db.transaction(|db| {
+            db.set_budget(300)?;
+            db.remove_all_etfs()?;
+            Err(SqliteError { code: None, message: Some("injected failure".into()) })
+        });
+
+        assert!(result.is_err());
+        assert_eq!(db.get_budget().unwrap(), Some(100));
+        assert_eq!(etf_ids(&db), vec!["AGGG.L".to_string()]);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_after_panic() {
+        let (db, path) = temp_db("transaction-panic");
+        db.set_budget(100).unwrap();
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _: Result<(), SqliteError> = db.transaction(|db| {
+                db.set_budget(300)?;
+                panic!("injected panic");
+            });
+        }));
+        assert!(result.is_err());
+        assert_eq!(db.get_budget().unwrap(), Some(100));
+
+        // With the savepoint still open this write would be discarded on close.
+        db.set_budget(200).unwrap();
+        drop(db);
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(db.get_budget().unwrap(), Some(200));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_rolls_back_when_release_fails() {
+        let (db, path) = temp_db("transaction-release-fails");
+        db.set_budget(100).unwrap();
+        // A deferred foreign key is only checked when the outermost savepoint is released.
+        db.connection.execute("
+            PRAGMA foreign_keys = ON;
+            CREATE TABLE parent (id INTEGER PRIMARY KEY);
+            CREATE TABLE child (parent_id INTEGER REFERENCES parent (id) DEFERRABLE INITIALLY DEFERRED);
+        ").unwrap();
+
+        let result: Result<(), SqliteError> = db.transaction(|db| {
+            db.set_budget(300)?;
+            db.connection.execute("INSERT INTO child (parent_id) VALUES (1);")
+        });
+        assert!(result.is_err());
+        assert_eq!(db.get_budget().unwrap(), Some(100));
+
+        db.set_budget(200).unwrap();
+        drop(db);
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(db.get_budget().unwrap(), Some(200));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_nested_transaction_rolls_back_inner_only() {
+        let (db, path) = temp_db("nested-transaction");
+
+        db.transaction(|db| {
+            db.set_budget(100)?;
+            let inner: Result<(), SqliteError> = db.transaction(|db| {
+                db.set_budget(200)?;
+                Err(SqliteError { code: None, message: Some("injected failure".into()) })
+            });
+            assert!(inner.is_err());
+            Ok::<(), SqliteError>(())
+        }).unwrap();
+
+        assert_eq!(db.get_budget().unwrap(), Some(100));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,186 @@
+use sqlite::Connection;
+
+use crate::SqliteError;
+
+// Each entry upgrades the schema from version `index` to `index + 1`. Never edit a released step,
+// append a new one instead. The first steps use IF NOT EXISTS because files written before
+// versioning already contain those tables while still reporting user_version 0.
+const MIGRATIONS: &[&str] = &[
+    "
+        CREATE TABLE IF NOT EXISTS etf (id TEXT PRIMARY KEY, isin TEXT, name TEXT, proportion FLOAT, cumulative INTEGER);
+        CREATE TABLE IF NOT EXISTS budget (id INTEGER PRIMARY KEY, budget INTEGER);
+        INSERT OR IGNORE INTO budget (id, budget) VALUES (0, 0);
+    ",
+    "
+        CREATE TABLE IF NOT EXISTS purchases (id INTEGER PRIMARY KEY AUTOINCREMENT, etf_id TEXT, quantity INTEGER, unit_price INTEGER, fees INTEGER, date TEXT);
+    ",
+    "
+        CREATE TABLE prices (ticker TEXT PRIMARY KEY, price FLOAT, currency TEXT, fetched_at INTEGER);
+    ",
+    "
+        CREATE TABLE price_history (
+            ticker TEXT, interval TEXT, timestamp INTEGER,
+            open FLOAT, high FLOAT, low FLOAT, close FLOAT, adj_close FLOAT, volume INTEGER,
+            PRIMARY KEY (ticker, interval, timestamp)
+        );
+        CREATE TABLE dividends (ticker TEXT, timestamp INTEGER, amount FLOAT, PRIMARY KEY (ticker, timestamp));
+    ",
+    "
+        ALTER TABLE budget ADD COLUMN currency TEXT NOT NULL DEFAULT 'EUR';
+    ",
+    "
+        ALTER TABLE etf ADD COLUMN fee_fixed INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE etf ADD COLUMN fee_basis_points INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+        ALTER TABLE etf ADD COLUMN min_quantity INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE etf ADD COLUMN max_quantity INTEGER;
+        ALTER TABLE etf ADD COLUMN lot_size INTEGER NOT NULL DEFAULT 1;
+        ALTER TABLE etf ADD COLUMN min_order_amount INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+        ALTER TABLE purchases ADD COLUMN quantity_decimals INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE budget ADD COLUMN fractional_decimals INTEGER;
+    ",
+    "
+        ALTER TABLE budget ADD COLUMN max_turnover_basis_points INTEGER;
+        ALTER TABLE budget ADD COLUMN sell_fee_fixed INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE budget ADD COLUMN sell_fee_basis_points INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+        ALTER TABLE budget ADD COLUMN target_market_value INTEGER NOT NULL DEFAULT 0;
+    ",
+    // The cumulative amounts included the purchases, which are now added when reading them. A purchase
+    // amount is rounded up to the cent, and dividing rounds towards zero.
+    "
+        UPDATE etf SET cumulative = cumulative - COALESCE((
+            SELECT SUM(
+                CASE WHEN quantity * unit_price < 0
+                    THEN quantity * unit_price / CAST(CAST('1e' || quantity_decimals AS REAL) AS INTEGER)
+                    ELSE (quantity * unit_price + CAST(CAST('1e' || quantity_decimals AS REAL) AS INTEGER) - 1) / CAST(CAST('1e' || quantity_decimals AS REAL) AS INTEGER)
+                END
+            )
+            FROM purchases WHERE etf_id = etf.id
+        ), 0);
+    ",
+];
+
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub fn user_version(connection: &Connection) -> Result<i64, SqliteError> {
+    let mut statement = connection.prepare("PRAGMA user_version;")?;
+    statement.next()?;
+    statement.read::<i64, _>(0)
+}
+
+pub fn migrate(connection: &Connection) -> Result<(), SqliteError> {
+    let version = user_version(connection)?;
+    if version > SCHEMA_VERSION {
+        return Err(SqliteError {
+            code: None,
+            message: Some(format!("the database has schema version {version}, but this library only supports up to version {SCHEMA_VERSION}")),
+        });
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to_version = from_version + 1;
+        connection.execute("BEGIN IMMEDIATE;")?;
+        let result = connection.execute(migration)
+            .and_then(|_| connection.execute(format!("PRAGMA user_version = {to_version};")));
+        match result {
+            Ok(()) => connection.execute("COMMIT;")?,
+            Err(e) => {
+                connection.execute("ROLLBACK;")?;
+                return Err(e);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Database;
+    use crate::tests::temp_path;
+
+    #[test]
+    fn test_new_database_is_at_latest_version() {
+        let path = temp_path("migrations-new");
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(user_version(&db.connection).unwrap(), SCHEMA_VERSION);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_from_unversioned_layout() {
+        let path = temp_path("migrations-unversioned");
+        {
+            let connection = sqlite::open(&path).unwrap();
+            connection.execute("
+                CREATE TABLE etf (id TEXT PRIMARY KEY, isin TEXT, name TEXT, proportion FLOAT, cumulative INTEGER);
+                CREATE TABLE budget (id INTEGER PRIMARY KEY, budget INTEGER);
+                INSERT INTO budget (id, budget) VALUES (0, 500);
+                INSERT INTO etf (id, isin, name, proportion, cumulative) VALUES ('IUSE.L', 'ISIN', 'NAME', 0.5, 100);
+            ").unwrap();
+        }
+
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(user_version(&db.connection).unwrap(), SCHEMA_VERSION);
+        assert_eq!(db.get_budget().unwrap(), Some(500));
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 100);
+        assert!(db.list_purchases().unwrap().is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_keeps_cumulative_with_purchases() {
+        let path = temp_path("migrations-purchases");
+        {
+            let connection = sqlite::open(&path).unwrap();
+            connection.execute("
+                CREATE TABLE etf (id TEXT PRIMARY KEY, isin TEXT, name TEXT, proportion FLOAT, cumulative INTEGER);
+                CREATE TABLE budget (id INTEGER PRIMARY KEY, budget INTEGER);
+                CREATE TABLE purchases (id INTEGER PRIMARY KEY AUTOINCREMENT, etf_id TEXT, quantity INTEGER, unit_price INTEGER, fees INTEGER, date TEXT);
+                INSERT INTO budget (id, budget) VALUES (0, 500);
+                INSERT INTO etf (id, isin, name, proportion, cumulative) VALUES ('IUSE.L', 'ISIN', 'NAME', 0.5, 10000);
+                INSERT INTO purchases (etf_id, quantity, unit_price, fees, date) VALUES ('IUSE.L', 3, 1000, 0, '2026-01-05');
+                INSERT INTO purchases (etf_id, quantity, unit_price, fees, date) VALUES ('IUSE.L', -1, 1200, 0, '2026-02-05');
+            ").unwrap();
+        }
+
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 10000);
+        assert_eq!(db.invested_amounts().unwrap()["IUSE.L"], 1800);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_upgrade_is_idempotent() {
+        let path = temp_path("migrations-idempotent");
+        drop(Database::new(path.to_str().unwrap()).unwrap());
+        let db = Database::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(user_version(&db.connection).unwrap(), SCHEMA_VERSION);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_refuse_newer_schema() {
+        let path = temp_path("migrations-newer");
+        {
+            let connection = sqlite::open(&path).unwrap();
+            connection.execute(format!("PRAGMA user_version = {};", SCHEMA_VERSION + 1)).unwrap();
+        }
+
+        let result = Database::new(path.to_str().unwrap());
+
+        assert!(result.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
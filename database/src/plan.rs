@@ -0,0 +1,302 @@
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use serde::{Deserialize, Serialize};
+use sqlite::Value;
+
+use crate::{Database, EtfData, PriceData, PurchaseData, RebalancingData, SqliteError, MAX_QUANTITY_DECIMALS};
+
+// The version of the plan document, raised whenever its fields change in a way older readers cannot follow.
+pub const PLAN_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum PlanError {
+    Sqlite(SqliteError),
+    Parse(String),
+    // A document that parses but would not make a usable plan.
+    Invalid(String),
+}
+
+impl Display for PlanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlanError::Sqlite(e) => write!(f, "database error: {e}"),
+            PlanError::Parse(message) => write!(f, "could not parse the plan: {message}"),
+            PlanError::Invalid(message) => write!(f, "invalid plan: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
+
+impl From<SqliteError> for PlanError {
+    fn from(e: SqliteError) -> Self {
+        PlanError::Sqlite(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanFormat {
+    Json,
+    Toml,
+}
+
+// Everything needed to move a plan to another machine: the settings, the purchase ledger and the cached prices.
+// The price history is left out, since it can be fetched again.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanDocument {
+    pub version: u32,
+    pub budget: i64,
+    pub currency: String,
+    pub fractional_decimals: Option<i64>,
+    pub rebalancing: Option<RebalancingData>,
+    pub target_market_value: bool,
+    pub etfs: Vec<EtfData>,
+    pub purchases: Vec<PurchaseData>,
+    pub prices: Vec<PriceData>,
+}
+
+impl PlanDocument {
+    pub fn to_string(&self, format: PlanFormat) -> Result<String, PlanError> {
+        match format {
+            PlanFormat::Json => serde_json::to_string_pretty(self).map_err(|e| PlanError::Parse(e.to_string())),
+            PlanFormat::Toml => toml::to_string(self).map_err(|e| PlanError::Parse(e.to_string())),
+        }
+    }
+
+    // Reads and validates a document.
+    pub fn from_str(document: &str, format: PlanFormat) -> Result<PlanDocument, PlanError> {
+        // The version is checked first, so a newer document fails on it rather than on a field it added.
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let version = match format {
+            PlanFormat::Json => serde_json::from_str::<Versioned>(document).map_err(|e| e.to_string()),
+            PlanFormat::Toml => toml::from_str::<Versioned>(document).map_err(|e| e.to_string()),
+        };
+        if let Ok(Versioned { version }) = version {
+            if version != PLAN_VERSION {
+                return Err(PlanError::Invalid(format!("version {version} is not supported, only version {PLAN_VERSION} is")));
+            }
+        }
+
+        let plan: PlanDocument = match format {
+            PlanFormat::Json => serde_json::from_str(document).map_err(|e| PlanError::Parse(e.to_string()))?,
+            PlanFormat::Toml => toml::from_str(document).map_err(|e| PlanError::Parse(e.to_string()))?,
+        };
+        plan.validate()?;
+        Ok(plan)
+    }
+
+    pub fn validate(&self) -> Result<(), PlanError> {
+        let invalid = |message: String| Err(PlanError::Invalid(message));
+        if self.version != PLAN_VERSION {
+            return invalid(format!("version {} is not supported, only version {PLAN_VERSION} is", self.version));
+        }
+        if self.budget < 0 {
+            return invalid("the budget is negative".to_string());
+        }
+        if self.currency.len() != 3 || !self.currency.chars().all(|c| c.is_ascii_uppercase()) {
+            return invalid(format!("{} is not a three letter currency code", self.currency));
+        }
+        if self.fractional_decimals.is_some_and(|decimals| !(0..=MAX_QUANTITY_DECIMALS as i64).contains(&decimals)) {
+            return invalid(format!("fractional shares have from 0 to {MAX_QUANTITY_DECIMALS} decimals"));
+        }
+        if self.rebalancing.is_some_and(|rebalancing| rebalancing.max_turnover_basis_points < 0 || rebalancing.sell_fee_fixed < 0
+            || rebalancing.sell_fee_basis_points < 0) {
+            return invalid("the turnover or sell fees of the rebalancing are negative".to_string());
+        }
+
+        let mut ids = HashSet::new();
+        for etf in &self.etfs {
+            if !ids.insert(etf.id.as_str()) {
+                return invalid(format!("{} is in the plan twice", etf.id));
+            }
+            if !etf.proportion.is_finite() || etf.proportion < 0.0 {
+                return invalid(format!("the proportion {} of {} is not a number of at least 0", etf.proportion, etf.id));
+            }
+            if etf.fee_fixed < 0 || etf.fee_basis_points < 0 || etf.min_quantity < 0 || etf.max_quantity.is_some_and(|max| max < 0)
+                || etf.lot_size < 1 || etf.min_order_amount < 0 {
+                return invalid(format!("the fees or order limits of {} are negative", etf.id));
+            }
+            if etf.cumulative < 0 {
+                return invalid(format!("the cumulative amount of {} is negative", etf.id));
+            }
+        }
+        if !self.etfs.is_empty() && self.etfs.iter().map(|etf| etf.proportion).sum::<f64>() <= 0.0 {
+            return invalid("the proportions add up to 0".to_string());
+        }
+        if let Some(purchase) = self.purchases.iter().find(|purchase| !ids.contains(purchase.etf_id.as_str())) {
+            return invalid(format!("purchase {} is of {}, which is not in the plan", purchase.id, purchase.etf_id));
+        }
+        for purchase in &self.purchases {
+            if purchase.quantity_decimals > MAX_QUANTITY_DECIMALS {
+                return invalid(format!("purchase {} has more than {MAX_QUANTITY_DECIMALS} decimals", purchase.id));
+            }
+            if purchase.unit_price < 0 || purchase.fees < 0 {
+                return invalid(format!("the price or fees of purchase {} are negative", purchase.id));
+            }
+        }
+        if let Some(price) = self.prices.iter().find(|price| !price.price.is_finite() || price.price <= 0.0) {
+            return invalid(format!("the price {} of {} is not positive", price.price, price.ticker));
+        }
+        Ok(())
+    }
+}
+
+impl Database {
+    pub fn plan_document(&self) -> Result<PlanDocument, SqliteError> {
+        Ok(PlanDocument {
+            version: PLAN_VERSION,
+            budget: self.get_budget()?.unwrap_or(0),
+            currency: self.get_currency()?.unwrap_or_else(|| "EUR".to_string()),
+            fractional_decimals: self.get_fractional_decimals()?,
+            rebalancing: self.get_rebalancing()?,
+            target_market_value: self.get_target_market_value()?,
+            etfs: self.get_all_etfs()?.collect::<Result<_, _>>()?,
+            purchases: self.list_purchases()?,
+            prices: self.list_prices()?,
+        })
+    }
+
+    pub fn export_plan(&self, format: PlanFormat) -> Result<String, PlanError> {
+        self.plan_document()?.to_string(format)
+    }
+
+    // Replaces the plan, the purchases and the cached prices with those of the document, all or nothing.
+    pub fn import_plan(&self, document: &str, format: PlanFormat) -> Result<PlanDocument, PlanError> {
+        let plan = PlanDocument::from_str(document, format)?;
+        self.restore_plan(&plan)?;
+        Ok(plan)
+    }
+
+    pub fn restore_plan(&self, plan: &PlanDocument) -> Result<(), PlanError> {
+        plan.validate()?;
+        self.transaction(|db| {
+            db.connection.execute("DELETE FROM purchases; DELETE FROM prices;")?;
+            db.replace_settings(plan.budget, plan.etfs.clone())?;
+            db.set_currency(&plan.currency)?;
+            db.set_fractional_decimals(plan.fractional_decimals)?;
+            db.set_rebalancing(plan.rebalancing)?;
+            db.set_target_market_value(plan.target_market_value)?;
+
+            // As they were, with the cumulative amounts of the etfs set again below since they include them.
+            for purchase in &plan.purchases {
+                let query = "
+                    INSERT INTO purchases (id, etf_id, quantity, quantity_decimals, unit_price, fees, date)
+                    VALUES (:id, :etf_id, :quantity, :quantity_decimals, :unit_price, :fees, :date);
+                ";
+                let mut statement = db.connection.prepare(query)?;
+                statement.bind::<&[(_, Value)]>(&[
+                    (":id", purchase.id.into()),
+                    (":etf_id", purchase.etf_id.as_str().into()),
+                    (":quantity", purchase.quantity.into()),
+                    (":quantity_decimals", (purchase.quantity_decimals as i64).into()),
+                    (":unit_price", purchase.unit_price.into()),
+                    (":fees", purchase.fees.into()),
+                    (":date", purchase.date.as_str().into()),
+                ])?;
+                statement.next()?;
+            }
+            for etf in &plan.etfs {
+                db.update_cumulative(&etf.id, etf.cumulative)?;
+            }
+            for price in &plan.prices {
+                db.store_price(price.clone())?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// Amounts are written as cents, e.g. 112_36.
+#[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_db;
+
+    fn plan_db(name: &str) -> (Database, std::path::PathBuf) {
+        let (db, path) = temp_db(name);
+        db.replace_settings(500_00, vec![
+            EtfData::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "S&P 500".into(), 0.7, 100_00).with_limits(1, Some(10), 1, 0),
+            EtfData::new("AGGG.L".into(), "IE00B3F81409".into(), "Aggregate Bond".into(), 0.3, 0).with_fee(1_00, 10),
+        ]).unwrap();
+        db.set_currency("GBP").unwrap();
+        db.set_rebalancing(Some(RebalancingData::new(500, 1_00, 0))).unwrap();
+        db.record_fractional_purchase("AGGG.L", 2_500, 3, 4_87, 1_00, "2026-01-05").unwrap();
+        db.store_price(PriceData::new("IUSE.L".into(), 112.36, Some("GBP".into()), 1_767_600_000)).unwrap();
+        (db, path)
+    }
+
+    #[test]
+    fn test_export_and_import_plan() {
+        let (db, path) = plan_db("export-plan");
+        let plan = db.plan_document().unwrap();
+        assert_eq!(plan.etfs[1].cumulative, 12_18);
+
+        for format in [PlanFormat::Json, PlanFormat::Toml] {
+            let document = db.export_plan(format).unwrap();
+            let (other, other_path) = temp_db("import-plan");
+            other.add_etf(EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 0)).unwrap();
+            other.record_purchase("IUSE.L", 1, 1, 0, "2020-01-01").unwrap();
+
+            assert_eq!(other.import_plan(&document, format).unwrap(), plan);
+            assert_eq!(other.plan_document().unwrap(), plan);
+            drop(other);
+            std::fs::remove_file(&other_path).unwrap();
+        }
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_import_rejects_invalid_plans() {
+        let (db, path) = plan_db("import-invalid-plan");
+        let plan = db.plan_document().unwrap();
+        let import = |plan: &PlanDocument| db.import_plan(&plan.to_string(PlanFormat::Json).unwrap(), PlanFormat::Json);
+
+        let mut newer = plan.clone();
+        newer.version = PLAN_VERSION + 1;
+        assert!(matches!(import(&newer), Err(PlanError::Invalid(_))));
+        let newer = format!("version = {}\nbudget = \"a field of a newer version\"", PLAN_VERSION + 1);
+        assert!(matches!(db.import_plan(&newer, PlanFormat::Toml), Err(PlanError::Invalid(_))));
+
+        let mut negative = plan.clone();
+        negative.etfs[0].proportion = -0.7;
+        assert!(matches!(import(&negative), Err(PlanError::Invalid(_))));
+        let mut zero = plan.clone();
+        zero.etfs.iter_mut().for_each(|etf| etf.proportion = 0.0);
+        assert!(matches!(import(&zero), Err(PlanError::Invalid(_))));
+        let mut unknown_etf = plan.clone();
+        unknown_etf.etfs.remove(1);
+        assert!(matches!(import(&unknown_etf), Err(PlanError::Invalid(_))));
+
+        let mut invalid_plans = vec![];
+        for decimals in [-1, MAX_QUANTITY_DECIMALS as i64 + 1] {
+            invalid_plans.push(PlanDocument { fractional_decimals: Some(decimals), ..plan.clone() });
+        }
+        invalid_plans.push(PlanDocument { rebalancing: Some(RebalancingData::new(-500, 1_00, 0)), ..plan.clone() });
+        invalid_plans.push(PlanDocument { rebalancing: Some(RebalancingData::new(500, 1_00, -1)), ..plan.clone() });
+        let mut negative_cumulative = plan.clone();
+        negative_cumulative.etfs[0].cumulative = -1;
+        invalid_plans.push(negative_cumulative);
+        let mut too_many_decimals = plan.clone();
+        too_many_decimals.purchases[0].quantity_decimals = MAX_QUANTITY_DECIMALS + 1;
+        invalid_plans.push(too_many_decimals);
+        for price in [0.0, -112.36] {
+            let mut non_positive_price = plan.clone();
+            non_positive_price.prices[0].price = price;
+            invalid_plans.push(non_positive_price);
+        }
+        for invalid_plan in &invalid_plans {
+            assert!(matches!(import(invalid_plan), Err(PlanError::Invalid(_))), "{invalid_plan:?}");
+        }
+        assert!(matches!(db.import_plan("{\"version\": 1}", PlanFormat::Json), Err(PlanError::Parse(_))));
+
+        // Nothing was changed by the rejected imports.
+        assert_eq!(db.plan_document().unwrap(), plan);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use derive_new::new;
+use serde::{Deserialize, Serialize};
+use sqlite::{Row, Value};
+
+use crate::{Database, SqliteError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct PriceData {
+    pub ticker: String,
+    pub price: f64,
+    pub currency: Option<String>,
+    // Unix time in seconds.
+    pub fetched_at: i64,
+}
+impl PriceData {
+    fn from_row(row: Row) -> PriceData {
+        let ticker: &str = row.read("ticker");
+        let price: f64 = row.read("price");
+        let currency: Option<&str> = row.read("currency");
+        let fetched_at: i64 = row.read("fetched_at");
+
+        PriceData::new(ticker.to_string(), price, currency.map(str::to_string), fetched_at)
+    }
+}
+
+impl Database {
+    pub fn store_price(&self, price: PriceData) -> Result<(), SqliteError> {
+        let query = "
+            INSERT OR REPLACE INTO prices (ticker, price, currency, fetched_at)
+            VALUES (:ticker, :price, :currency, :fetched_at);
+        ";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[
+            (":ticker", price.ticker.into()),
+            (":price", price.price.into()),
+            (":currency", price.currency.map_or(Value::Null, Value::from)),
+            (":fetched_at", price.fetched_at.into()),
+        ])?;
+        statement.next()?;
+        Ok(())
+    }
+
+    pub fn get_price(&self, ticker: &str) -> Result<Option<PriceData>, SqliteError> {
+        let query = "SELECT ticker, price, currency, fetched_at FROM prices WHERE ticker = :ticker";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":ticker", ticker.into())])?;
+        statement.into_iter().map(|row| row.map(PriceData::from_row)).next().transpose()
+    }
+
+    pub fn list_prices(&self) -> Result<Vec<PriceData>, SqliteError> {
+        let query = "SELECT ticker, price, currency, fetched_at FROM prices ORDER BY ticker";
+        let statement = self.connection.prepare(query)?;
+        statement.into_iter().map(|row| row.map(PriceData::from_row)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::temp_db;
+
+    #[test]
+    fn test_store_price_replaces_older_quote() {
+        let (db, path) = temp_db("store-price");
+
+        db.store_price(PriceData::new("IUSE.L".into(), 110.0, Some("EUR".into()), 1_700_000_000)).unwrap();
+        db.store_price(PriceData::new("IUSE.L".into(), 112.5, Some("EUR".into()), 1_700_086_400)).unwrap();
+        db.store_price(PriceData::new("AGGG.L".into(), 4.87, None, 1_700_000_000)).unwrap();
+
+        assert_eq!(db.get_price("IUSE.L").unwrap(), Some(PriceData::new("IUSE.L".into(), 112.5, Some("EUR".into()), 1_700_086_400)));
+        assert_eq!(db.get_price("AGGG.L").unwrap().unwrap().currency, None);
+        assert_eq!(db.get_price("IWDA.AS").unwrap(), None);
+        assert_eq!(db.list_prices().unwrap().len(), 2);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
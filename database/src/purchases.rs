@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use derive_new::new;
+use serde::{Deserialize, Serialize};
+use sqlite::{Row, Value};
+
+use crate::{Database, SqliteError};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, new)]
+pub struct PurchaseData {
+    pub id: i64,
+    pub etf_id: String,
+    // In units of 10^-quantity_decimals shares.
+    pub quantity: i64,
+    pub unit_price: i64,
+    pub fees: i64,
+    pub date: String,
+    #[new(default)]
+    #[serde(default)]
+    pub quantity_decimals: u32,
+}
+impl PurchaseData {
+    // Rounded up to the cent for fractional quantities.
+    pub fn amount(&self) -> i64 {
+        fractional_amount(self.quantity, self.quantity_decimals, self.unit_price)
+    }
+
+    pub fn units(&self) -> f64 {
+        self.quantity as f64 / 10f64.powi(self.quantity_decimals as i32)
+    }
+
+    fn from_row(row: Row) -> PurchaseData {
+        let id: i64 = row.read("id");
+        let etf_id: &str = row.read("etf_id");
+        let quantity: i64 = row.read("quantity");
+        let unit_price: i64 = row.read("unit_price");
+        let fees: i64 = row.read("fees");
+        let date: &str = row.read("date");
+        let quantity_decimals: i64 = row.read("quantity_decimals");
+
+        PurchaseData { quantity_decimals: quantity_decimals as u32, ..PurchaseData::new(id, etf_id.to_string(), quantity, unit_price, fees, date.to_string()) }
+    }
+}
+
+fn fractional_amount(quantity: i64, quantity_decimals: u32, unit_price: i64) -> i64 {
+    let scale = 10i64.pow(quantity_decimals);
+    (quantity * unit_price + scale - 1).div_euclid(scale)
+}
+
+impl Database {
+    pub fn record_purchase(&self, etf_id: &str, quantity: i64, unit_price: i64, fees: i64, date: &str) -> Result<i64, SqliteError> {
+        self.record_fractional_purchase(etf_id, quantity, 0, unit_price, fees, date)
+    }
+
+    // Records a purchase of quantity times 10^-quantity_decimals shares.
+    pub fn record_fractional_purchase(&self, etf_id: &str, quantity: i64, quantity_decimals: u32, unit_price: i64, fees: i64, date: &str) -> Result<i64, SqliteError> {
+        self.transaction(|db| {
+            if db.get_etf(etf_id)?.is_none() {
+                return Err(SqliteError { code: None, message: Some(format!("{etf_id} is not one of the configured etfs")) });
+            }
+            let query = "
+                INSERT INTO purchases (etf_id, quantity, quantity_decimals, unit_price, fees, date)
+                VALUES (:etf_id, :quantity, :quantity_decimals, :unit_price, :fees, :date);
+            ";
+            let mut statement = db.connection.prepare(query)?;
+            statement.bind::<&[(_, Value)]>(&[
+                (":etf_id", etf_id.into()),
+                (":quantity", quantity.into()),
+                (":quantity_decimals", (quantity_decimals as i64).into()),
+                (":unit_price", unit_price.into()),
+                (":fees", fees.into()),
+                (":date", date.into()),
+            ])?;
+            statement.next()?;
+
+            db.last_insert_rowid()
+        })
+    }
+
+    pub fn list_purchases(&self) -> Result<Vec<PurchaseData>, SqliteError> {
+        let query = "SELECT id, etf_id, quantity, quantity_decimals, unit_price, fees, date FROM purchases ORDER BY date, id";
+        let statement = self.connection.prepare(query)?;
+        statement.into_iter().map(|row| row.map(PurchaseData::from_row)).collect()
+    }
+
+    // The shares held of each etf with purchases, adding up the ledger with sells counted negative.
+    pub fn shares_held(&self) -> Result<HashMap<String, f64>, SqliteError> {
+        let mut shares = HashMap::new();
+        for purchase in self.list_purchases()? {
+            *shares.entry(purchase.etf_id.clone()).or_insert(0.0) += purchase.units();
+        }
+        Ok(shares)
+    }
+
+    pub fn get_purchase(&self, purchase_id: i64) -> Result<Option<PurchaseData>, SqliteError> {
+        let query = "SELECT id, etf_id, quantity, quantity_decimals, unit_price, fees, date FROM purchases WHERE id = :id";
+        let mut statement = self.connection.prepare(query)?;
+        statement.bind::<&[(_, Value)]>(&[(":id", purchase_id.into())])?;
+        statement.into_iter().map(|row| row.map(PurchaseData::from_row)).next().transpose()
+    }
+
+    // The amount invested in each etf with purchases, which is added to its cumulative amount.
+    pub(crate) fn invested_amounts(&self) -> Result<HashMap<String, i64>, SqliteError> {
+        let mut amounts = HashMap::new();
+        for purchase in self.list_purchases()? {
+            *amounts.entry(purchase.etf_id.clone()).or_insert(0) += purchase.amount();
+        }
+        Ok(amounts)
+    }
+
+    pub fn undo_purchase(&self, purchase_id: i64) -> Result<Option<PurchaseData>, SqliteError> {
+        self.transaction(|db| {
+            let Some(purchase) = db.get_purchase(purchase_id)? else {
+                return Ok(None);
+            };
+
+            let query = "DELETE FROM purchases WHERE id = :id;";
+            let mut statement = db.connection.prepare(query)?;
+            statement.bind::<&[(_, Value)]>(&[(":id", purchase_id.into())])?;
+            statement.next()?;
+            Ok(Some(purchase))
+        })
+    }
+
+    fn last_insert_rowid(&self) -> Result<i64, SqliteError> {
+        let mut statement = self.connection.prepare("SELECT last_insert_rowid()")?;
+        statement.next()?;
+        statement.read::<i64, _>(0)
+    }
+}
+
+// Amounts are written as cents, e.g. 112_36.
+#[cfg(test)]
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use crate::tests::temp_db;
+    use crate::EtfData;
+
+    #[test]
+    fn test_record_purchase_updates_cumulative() {
+        let (db, path) = temp_db("record-purchase");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 100_00)).unwrap();
+
+        let id = db.record_purchase("IUSE.L", 3, 10_00, 2_00, "2026-01-05").unwrap();
+        db.record_purchase("IUSE.L", 1, 12_00, 2_00, "2026-02-05").unwrap();
+
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 142_00);
+        let purchases = db.list_purchases().unwrap();
+        assert_eq!(purchases.len(), 2);
+        assert_eq!(purchases[0], super::PurchaseData::new(id, "IUSE.L".into(), 3, 10_00, 2_00, "2026-01-05".into()));
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_undo_purchase_restores_cumulative() {
+        let (db, path) = temp_db("undo-purchase");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 100_00)).unwrap();
+        let id = db.record_purchase("IUSE.L", 3, 10_00, 0, "2026-01-05").unwrap();
+
+        let undone = db.undo_purchase(id).unwrap().unwrap();
+
+        assert_eq!(undone.quantity, 3);
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 100_00);
+        assert!(db.list_purchases().unwrap().is_empty());
+        assert!(db.undo_purchase(id).unwrap().is_none());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_cumulative_survives_replacing_settings() {
+        let (db, path) = temp_db("purchase-replace-settings");
+        let etf = EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 100_00);
+        db.add_etf(etf.clone()).unwrap();
+        db.record_purchase("IUSE.L", 3, 10_00, 0, "2026-01-05").unwrap();
+
+        // Settings read before the purchase, which must not undo it.
+        db.replace_settings(500_00, vec![EtfData { proportion: 0.5, ..etf }]).unwrap();
+
+        let etf = db.get_etf("IUSE.L").unwrap().unwrap();
+        assert_eq!((etf.proportion, etf.cumulative), (0.5, 130_00));
+        db.update_cumulative("IUSE.L", 120_00).unwrap();
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 120_00);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_removing_an_etf_removes_its_purchases() {
+        let (db, path) = temp_db("purchase-remove-etf");
+        let etf = EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 100_00);
+        let other = EtfData::new("AGGG.L".into(), "ISIN".into(), "NAME".into(), 1.0, 0);
+        db.replace_settings(500_00, vec![etf.clone(), other.clone()]).unwrap();
+        db.record_purchase("IUSE.L", 3, 10_00, 0, "2026-01-05").unwrap();
+        db.record_purchase("AGGG.L", 2, 5_00, 0, "2026-01-05").unwrap();
+
+        db.replace_settings(500_00, vec![other.clone()]).unwrap();
+        assert_eq!(db.list_purchases().unwrap().iter().map(|p| p.etf_id.as_str()).collect::<Vec<_>>(), vec!["AGGG.L"]);
+        db.add_etf(etf).unwrap();
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 100_00);
+
+        db.remove_etf("AGGG.L".into()).unwrap();
+        assert!(db.list_purchases().unwrap().is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_purchase_of_unknown_etf() {
+        let (db, path) = temp_db("purchase-unknown-etf");
+        assert!(db.record_purchase("IUSE.L", 3, 10_00, 0, "2026-01-05").is_err());
+        assert!(db.list_purchases().unwrap().is_empty());
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_record_fractional_purchase() {
+        let (db, path) = temp_db("record-fractional-purchase");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 0)).unwrap();
+
+        let id = db.record_fractional_purchase("IUSE.L", 33_333, 3, 3_00, 0, "2026-01-05").unwrap();
+
+        let purchase = db.get_purchase(id).unwrap().unwrap();
+        assert_eq!((purchase.quantity, purchase.quantity_decimals), (33_333, 3));
+        assert_eq!(purchase.amount(), 100_00);
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 100_00);
+        db.undo_purchase(id).unwrap();
+        assert_eq!(db.get_etf("IUSE.L").unwrap().unwrap().cumulative, 0);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shares_held() {
+        let (db, path) = temp_db("shares-held");
+        db.add_etf(EtfData::new("IUSE.L".into(), "ISIN".into(), "NAME".into(), 1.0, 0)).unwrap();
+        db.add_etf(EtfData::new("AGGG.L".into(), "ISIN".into(), "NAME".into(), 1.0, 0)).unwrap();
+        db.record_purchase("IUSE.L", 3, 10_00, 0, "2026-01-05").unwrap();
+        db.record_fractional_purchase("IUSE.L", 1_250, 3, 10_00, 0, "2026-02-05").unwrap();
+        db.record_purchase("IUSE.L", -2, 12_00, 0, "2026-03-05").unwrap();
+        db.record_purchase("AGGG.L", 4, 5_00, 0, "2026-03-05").unwrap();
+
+        let shares = db.shares_held().unwrap();
+        assert_eq!(shares.len(), 2);
+        assert_eq!(shares["IUSE.L"], 2.25);
+        assert_eq!(shares["AGGG.L"], 4.0);
+        drop(db);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
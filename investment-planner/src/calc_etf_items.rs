@@ -43,7 +43,8 @@ pub fn calc_etf_items(settings: &Settings, prices: &[f64]) -> Vec<EtfItem> {
     amounts.iter()
         .zip(targets)
         .zip(prices)
-        .map(|((&cumulative, target), &price)| EtfItem::new(cumulative, target as i64, price as i64))
+        .zip(&settings.etf_settings)
+        .map(|(((&cumulative, target), &price), etf)| EtfItem::new(cumulative, target as i64, price as i64).with_fee(etf.fee).with_limits(etf.limits))
         .collect()
 }
 
@@ -0,0 +1,58 @@
+use derive_new::new;
+use crate::calc_etf_items::calc_etf_items;
+use crate::{Action, Investment, Settings};
+
+// Why an etf got what it got: where it stands, where the plan wants it and where the investments leave it.
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct Drift {
+    pub current_proportion: f64,
+    pub ideal_proportion: f64,
+    // The amount the etf is aimed at after this budget is spent, as given to the solver.
+    pub target: i64,
+    pub post_purchase_proportion: f64,
+    // The etf's term of the solver's objective: how far it ends up from its target, squared, in cents squared.
+    pub residual_error: f64,
+}
+
+fn proportions(xs: &[f64]) -> Vec<f64> {
+    let total: f64 = xs.iter().sum();
+    xs.iter().map(|x| if total > 0.0 { x / total } else { 0.0 }).collect()
+}
+
+// The settings the solver got when the investments include sells: each sold etf holds less, and the budget
+// has what the sells brought in after their fees, as rebalance_investments does.
+fn settings_after_sells(settings: &Settings, investments: &[Investment]) -> Settings {
+    let mut settings = settings.clone();
+    for sell in investments.iter().filter(|investment| investment.action == Action::Sell) {
+        if let Some(etf) = settings.etf_settings.iter_mut().find(|etf| etf.id == sell.etf_id) {
+            etf.cumulative -= sell.amount();
+            settings.budget += sell.amount() - sell.fee;
+        }
+    }
+    settings
+}
+
+// One drift per etf of the settings, in their order, taking in every investment on the etf whether a buy or a sell.
+// The targets are those the solver aimed at, after any sells.
+pub fn drift_report(settings: &Settings, prices: &[f64], investments: &[Investment]) -> Vec<Drift> {
+    let items = calc_etf_items(&settings_after_sells(settings, investments), prices);
+    let post_amounts = settings.etf_settings.iter().map(|etf| {
+        let invested: i64 = investments.iter()
+            .filter(|investment| investment.etf_id == etf.id)
+            .map(|investment| match investment.action {
+                Action::Buy => investment.amount(),
+                Action::Sell => -investment.amount(),
+            })
+            .sum();
+        (etf.cumulative + invested) as f64
+    }).collect::<Vec<_>>();
+
+    let current = proportions(&settings.etf_settings.iter().map(|etf| etf.cumulative as f64).collect::<Vec<_>>());
+    let ideal = proportions(&settings.etf_settings.iter().map(|etf| etf.ideal_proportion).collect::<Vec<_>>());
+    let post_purchase = proportions(&post_amounts);
+
+    items.iter().enumerate().map(|(i, item)| {
+        let residual_error = (item.target as f64 - post_amounts[i]).powi(2);
+        Drift::new(current[i], ideal[i], item.target, post_purchase[i], residual_error)
+    }).collect()
+}
@@ -0,0 +1,72 @@
+use investment_strategy::EtfItem;
+use crate::calc_etf_items::calc_etf_items;
+use crate::{Investment, Settings};
+
+// The largest precision supported, which keeps quantities in 10^-decimals units far from overflowing.
+pub const MAX_DECIMALS: u32 = 8;
+
+// An order of `amount` cents, rounded down to the precision, as (quantity in 10^-decimals units, amount actually spent).
+fn order(etf: &EtfItem, amount: i64, scale: i64) -> (i64, i64) {
+    if etf.price <= 0 {
+        return (0, 0);
+    }
+    let quantity = amount.max(0) as i128 * scale as i128 / etf.price as i128;
+    let spent = (quantity * etf.price as i128 + scale as i128 - 1) / scale as i128;
+    (quantity as i64, spent as i64)
+}
+
+// Splits the budget over the etfs in proportion to how far each is below its target, after the fees.
+// An order that would exceed the etf's maximum quantity is capped, and one below its minimum amount or
+// quantity is dropped, after which the rest of the budget is split again over the remaining etfs.
+// Lot sizes are meant for whole shares and do not apply here.
+pub fn fractional_investments(settings: Settings, prices: &[f64], decimals: u32) -> Vec<Investment> {
+    let decimals = decimals.min(MAX_DECIMALS);
+    let scale = 10i64.pow(decimals);
+    let items = calc_etf_items(&settings, prices);
+    let directions = items.iter().map(|etf| (etf.target - etf.cumulative).max(0)).collect::<Vec<_>>();
+
+    // None while the etf still takes part in the split, otherwise its final amount.
+    let mut decided = items.iter().zip(&directions)
+        .map(|(etf, &direction)| (direction == 0 || etf.price <= 0 || etf.limits.max_quantity == Some(0)).then_some(0))
+        .collect::<Vec<Option<i64>>>();
+    let amounts = loop {
+        let decided_spent: i64 = items.iter().zip(&decided)
+            .filter_map(|(etf, amount)| amount.map(|amount| amount + etf.fee.of(amount)))
+            .sum();
+        let open = (0..items.len()).filter(|&i| decided[i].is_none()).collect::<Vec<_>>();
+        let fixed_fees: i64 = open.iter().map(|&i| items[i].fee.fixed).sum();
+        let remaining = settings.budget - decided_spent - fixed_fees;
+        let total_direction: i64 = open.iter().map(|&i| directions[i]).sum();
+        if open.is_empty() || remaining <= 0 {
+            break decided.iter().map(|amount| amount.unwrap_or(0)).collect::<Vec<_>>();
+        }
+
+        let mut amounts = decided.clone();
+        let mut changed = false;
+        for &i in &open {
+            let etf = &items[i];
+            let share = directions[i] as f64 * remaining as f64 / total_direction as f64;
+            // Keep a cent back when the percentage fee is rounded up.
+            let reserve = if etf.fee.basis_points > 0 { 1.0 } else { 0.0 };
+            let amount = ((share - reserve) / (1.0 + etf.fee.basis_points as f64 / 10_000.0)).floor() as i64;
+            let (quantity, spent) = order(etf, amount, scale);
+
+            if let Some(max_quantity) = etf.limits.max_quantity.filter(|&max_quantity| quantity > max_quantity * scale) {
+                decided[i] = Some((max_quantity * etf.price).min(amount));
+                changed = true;
+            } else if quantity == 0 || spent < etf.limits.min_amount || quantity < etf.limits.min_quantity * scale {
+                decided[i] = Some(0);
+                changed = true;
+            }
+            amounts[i] = Some(amount);
+        }
+        if !changed {
+            break amounts.iter().map(|amount| amount.unwrap_or(0)).collect::<Vec<_>>();
+        }
+    };
+
+    items.iter().zip(amounts).zip(settings.etf_settings).map(|((etf, amount), etf_setting)| {
+        let (quantity, spent) = order(etf, amount, scale);
+        Investment { fee: etf.fee.of(spent), decimals, ..Investment::new(etf_setting.id, etf_setting.name, quantity, etf.price) }
+    }).collect()
+}
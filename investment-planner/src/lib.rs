@@ -1,8 +1,20 @@
 mod calc_etf_items;
+mod drift;
+mod fractional;
+mod rebalance;
+mod simulation;
 
 use investment_strategy::solve_etf_problem;
 use derive_new::new;
 use crate::calc_etf_items::calc_etf_items;
+use crate::fractional::fractional_investments;
+
+pub use crate::drift::{drift_report, Drift};
+pub use crate::fractional::MAX_DECIMALS;
+pub use crate::rebalance::{rebalance_investments, Rebalancing};
+pub use crate::simulation::{PricePath, SimulatedMonth, Simulation, SimulationError};
+
+pub use investment_strategy::{Fee, OrderLimits};
 
 pub type EtfId = String;
 
@@ -10,8 +22,45 @@ pub type EtfId = String;
 pub struct Investment {
     pub etf_id: EtfId,
     pub name: String,
+    // In units of 10^-decimals shares, so whole shares unless the investment is fractional.
     pub quantity: i64,
     pub price: i64,
+    // What the broker charges for the order, on top of quantity times price.
+    #[new(default)]
+    pub fee: i64,
+    #[new(default)]
+    pub decimals: u32,
+    #[new(default)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Action {
+    #[default]
+    Buy,
+    // Only proposed when rebalancing, the quantity is then what to sell.
+    Sell,
+}
+
+impl Investment {
+    pub fn units(&self) -> f64 {
+        self.quantity as f64 / 10f64.powi(self.decimals as i32)
+    }
+
+    // What the shares cost, rounded up to the cent.
+    pub fn amount(&self) -> i64 {
+        let scale = 10i64.pow(self.decimals);
+        (self.quantity * self.price + scale - 1).div_euclid(scale)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Allocation {
+    // Whole shares, as close to the targets as the budget allows.
+    #[default]
+    WholeShares,
+    // Fractions of shares down to `decimals` decimal places, spending the budget in proportion to the targets.
+    Fractional { decimals: u32 },
 }
 
 #[derive(Debug, Clone, PartialEq, new)]
@@ -20,7 +69,37 @@ pub struct EtfSetting {
     pub isin: String,
     pub name: String,
     pub ideal_proportion: f64,
-    pub cumulative: i64
+    pub cumulative: i64,
+    #[new(default)]
+    pub fee: Fee,
+    #[new(default)]
+    pub limits: OrderLimits,
+    // The shares held, which only matter when valuing the holdings at the market price.
+    #[new(default)]
+    pub shares: f64,
+}
+
+impl EtfSetting {
+    pub fn with_fee(self, fee: Fee) -> Self {
+        Self { fee, ..self }
+    }
+
+    pub fn with_limits(self, limits: OrderLimits) -> Self {
+        Self { limits, ..self }
+    }
+
+    pub fn with_shares(self, shares: f64) -> Self {
+        Self { shares, ..self }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub enum Valuation {
+    // What was put into each etf, its cumulative amount.
+    #[default]
+    CostBasis,
+    // What each etf is worth now, its shares times the latest price.
+    MarketValue,
 }
 
 #[derive(Debug, Clone, PartialEq, new)]
@@ -29,6 +108,19 @@ pub struct Settings {
     pub etf_settings: Vec<EtfSetting>,
 }
 
+impl Settings {
+    // The settings with each cumulative amount replaced by what the etf is worth under `valuation`, so the
+    // targets are computed against it.
+    pub fn valued_at(mut self, valuation: Valuation, prices: &[f64]) -> Settings {
+        if valuation == Valuation::MarketValue {
+            for (etf, price) in self.etf_settings.iter_mut().zip(prices) {
+                etf.cumulative = (etf.shares * price).round() as i64;
+            }
+        }
+        self
+    }
+}
+
 pub fn next_investments(settings: Settings, prices: &[f64]) -> Vec<Investment> {
     assert!(prices.iter().all(|&p| p > 0.0));
     let items = calc_etf_items(&settings, prices);
@@ -36,18 +128,42 @@ pub fn next_investments(settings: Settings, prices: &[f64]) -> Vec<Investment> {
 
     let investments = solution.into_iter()
             .zip(settings.etf_settings)
-            .map(|((item, quantity), etf_setting)| Investment::new(etf_setting.id, etf_setting.name, quantity, item.price));
+            .map(|((item, quantity), etf_setting)| Investment {
+                fee: item.fee.of(item.price * quantity),
+                ..Investment::new(etf_setting.id, etf_setting.name, quantity, item.price)
+            });
     investments.collect()
 }
 
+pub fn next_investments_with(allocation: Allocation, settings: Settings, prices: &[f64]) -> Vec<Investment> {
+    match allocation {
+        Allocation::WholeShares => next_investments(settings, prices),
+        Allocation::Fractional { decimals } => {
+            assert!(prices.iter().all(|&p| p > 0.0));
+            fractional_investments(settings, prices, decimals)
+        }
+    }
+}
+
+pub fn total_fees(investments: &[Investment]) -> i64 {
+    investments.iter().map(|i| i.fee).sum()
+}
+
 pub fn total_amount_spent(investments: &[Investment], prices: &[f64]) -> f64 {
-    investments.iter().zip(prices).map(|(i, p)| i.quantity as f64 * p).sum()
+    investments.iter().zip(prices).map(|(i, p)| i.units() * p).sum()
 }
 
 pub fn left_over_budget(budget: i64, investments: &[Investment], prices: &[f64]) -> i64 {
     budget - total_amount_spent(&investments, &prices) as i64
 }
 
+// What is left of the budget once the buys and all fees are paid, with what the sells bring in added.
+pub fn left_over_cash(budget: i64, investments: &[Investment], prices: &[f64]) -> i64 {
+    let (buys, sells): (Vec<_>, Vec<_>) = investments.iter().cloned().partition(|i| i.action == Action::Buy);
+    let proceeds: i64 = sells.iter().map(Investment::amount).sum();
+    left_over_budget(budget, &buys, prices) + proceeds - total_fees(investments)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,7 +173,7 @@ mod tests {
         let settings = Settings::new(600_00, vec![EtfSetting::new("ID1".into(), "".to_string(), "".to_string(),0.5, 100_00)]);
         let prices = vec![5_00f64];
         let investments = next_investments(settings, &prices);
-        assert_eq!(investments, vec![Investment::new("ID1".into(), "".to_string(), "".to_string(),120)])
+        assert_eq!(investments, vec![Investment::new("ID1".into(), "".to_string(), 120, 5_00)])
     }
 
     #[test]
@@ -70,9 +186,9 @@ mod tests {
         let prices = vec![5_00f64, 5_00f64, 5_00f64];
         let investments = next_investments(settings, &prices);
         assert_eq!(investments, vec![
-            Investment::new("ID1".into(), "".to_string(), 20),
-            Investment::new("ID2".into(), "".to_string(),60),
-            Investment::new("ID3".into(), "".to_string(),20),
+            Investment::new("ID1".into(), "".to_string(), 20, 5_00),
+            Investment::new("ID2".into(), "".to_string(), 60, 5_00),
+            Investment::new("ID3".into(), "".to_string(), 20, 5_00),
         ])
     }
 
@@ -97,5 +213,207 @@ mod tests {
         assert_eq!(total_amount_spent(&investments, &prices), 99_00.0);
         assert_eq!(left_over_budget(100_00, &investments, &prices), 1_00);
     }
-}
 
+    #[test]
+    fn test_next_investments_with_fees() {
+        let fee = Fee::new(1_00, 10);
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 0).with_fee(fee),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 0).with_fee(fee),
+        ]);
+        let prices = vec![10_00f64, 5_00f64];
+        let investments = next_investments(settings, &prices);
+
+        assert_eq!(investments, vec![
+            Investment { fee: 1_05, ..Investment::new("ID1".into(), "".to_string(), 5, 10_00) },
+            Investment { fee: 1_05, ..Investment::new("ID2".into(), "".to_string(), 9, 5_00) },
+        ]);
+        assert_eq!(total_fees(&investments), 2_10);
+        assert!(total_amount_spent(&investments, &prices) as i64 + total_fees(&investments) <= 100_00);
+    }
+
+    #[test]
+    fn test_next_investments_with_limits() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 0).with_limits(OrderLimits::new(0, None, 3, 0)),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 0).with_limits(OrderLimits::new(0, Some(4), 1, 0)),
+        ]);
+        let prices = vec![5_00f64, 5_00f64];
+        let investments = next_investments(settings, &prices);
+
+        assert_eq!(investments.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![9, 4]);
+    }
+
+    #[test]
+    fn test_next_investments_fractional() {
+        let settings = Settings::new(100_00, vec![EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 1.0, 0)]);
+        let prices = vec![3_00f64];
+        let investments = next_investments_with(Allocation::Fractional { decimals: 3 }, settings, &prices);
+
+        assert_eq!(investments, vec![Investment { decimals: 3, ..Investment::new("ID1".into(), "".to_string(), 33_333, 3_00) }]);
+        assert_eq!(investments[0].amount(), 100_00);
+        assert_eq!(investments[0].units(), 33.333);
+    }
+
+    #[test]
+    fn test_next_investments_fractional_spends_more_than_whole_shares() {
+        let etf_settings = vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(),0.25, 25_00),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(),0.25, 25_00),
+            EtfSetting::new("ID3".into(), "".to_string(), "".to_string(),0.5, 50_00),
+        ];
+        let prices = vec![7_00f64, 9_00f64, 3_00f64];
+        let investments = next_investments_with(Allocation::Fractional { decimals: 2 }, Settings::new(100_00, etf_settings), &prices);
+
+        assert_eq!(investments.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![357, 277, 1666]);
+        assert_eq!(investments.iter().map(|i| i.amount()).sum::<i64>(), 99_90);
+        assert!(left_over_budget(100_00, &investments, &prices) < 1_00);
+    }
+
+    #[test]
+    fn test_next_investments_fractional_with_fees_and_limits() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 0).with_limits(OrderLimits::new(0, Some(2), 1, 0)),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 0).with_fee(Fee::new(1_00, 0)),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        let investments = next_investments_with(Allocation::Fractional { decimals: 2 }, settings, &prices);
+
+        assert_eq!(investments.iter().map(|i| (i.quantity, i.fee)).collect::<Vec<_>>(), vec![(200, 0), (790, 1_00)]);
+        assert_eq!(investments.iter().map(|i| i.amount() + i.fee).sum::<i64>(), 100_00);
+    }
+
+    #[test]
+    fn test_next_investments_fractional_drops_orders_below_minimum() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.9, 0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.1, 0).with_limits(OrderLimits::new(0, None, 1, 20_00)),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        let investments = next_investments_with(Allocation::Fractional { decimals: 1 }, settings, &prices);
+
+        assert_eq!(investments.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![100, 0]);
+    }
+
+    #[test]
+    fn test_rebalance_sells_overweight_etf() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 300_00).with_shares(30.0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 100_00).with_shares(10.0),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        let investments = rebalance_investments(Rebalancing::new(100_00, Fee::default()), Allocation::WholeShares, settings, &prices);
+
+        assert_eq!(investments, vec![
+            Investment::new("ID1".into(), "".to_string(), 0, 10_00),
+            Investment::new("ID2".into(), "".to_string(), 15, 10_00),
+            Investment { action: Action::Sell, ..Investment::new("ID1".into(), "".to_string(), 5, 10_00) },
+        ]);
+    }
+
+    #[test]
+    fn test_rebalance_within_turnover_limit_and_sell_fee() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 300_00).with_shares(30.0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 100_00).with_shares(10.0),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        // 5% of the 400_00 held may be sold, and the fee comes out of the proceeds.
+        let investments = rebalance_investments(Rebalancing::new(5_00, Fee::new(1_00, 0)), Allocation::WholeShares, settings, &prices);
+
+        assert_eq!(investments.iter().map(|i| (i.etf_id.as_str(), i.action, i.quantity, i.fee)).collect::<Vec<_>>(), vec![
+            ("ID1", Action::Buy, 0, 0),
+            ("ID2", Action::Buy, 11, 0),
+            ("ID1", Action::Sell, 2, 1_00),
+        ]);
+    }
+
+    #[test]
+    fn test_rebalance_sells_at_most_the_shares_held() {
+        // The 30 shares of ID1 were bought at 10_00 and are now worth 1_00 each, far less than its cumulative.
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 300_00).with_shares(30.0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 0),
+        ]);
+        let prices = vec![1_00f64, 10_00f64];
+        let investments = rebalance_investments(Rebalancing::new(100_00, Fee::default()), Allocation::WholeShares, settings, &prices);
+
+        assert_eq!(investments.iter().map(|i| (i.etf_id.as_str(), i.action, i.quantity)).collect::<Vec<_>>(), vec![
+            ("ID1", Action::Buy, 0),
+            ("ID2", Action::Buy, 13),
+            ("ID1", Action::Sell, 30),
+        ]);
+    }
+
+    #[test]
+    fn test_rebalance_without_overweight_only_buys() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 100_00),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 100_00),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        let rebalanced = rebalance_investments(Rebalancing::new(100_00, Fee::default()), Allocation::WholeShares, settings.clone(), &prices);
+
+        assert_eq!(rebalanced, next_investments(settings, &prices));
+    }
+
+    #[test]
+    fn test_next_investments_at_market_value() {
+        // Both had the same put in, but ID1 has doubled since.
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 100_00).with_shares(10.0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 100_00).with_shares(10.0),
+        ]);
+        let prices = vec![20_00f64, 10_00f64];
+
+        let at_cost = next_investments(settings.clone().valued_at(Valuation::CostBasis, &prices), &prices);
+        assert_eq!(at_cost.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![2, 5]);
+
+        let at_market = next_investments(settings.valued_at(Valuation::MarketValue, &prices), &prices);
+        assert_eq!(at_market.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![0, 10]);
+    }
+
+    #[test]
+    fn test_drift_report() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.75, 300_00),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.25, 100_00),
+        ]);
+        let prices = vec![30_00f64, 9_00f64];
+        let investments = next_investments(settings.clone(), &prices);
+        assert_eq!(investments.iter().map(|i| i.quantity).collect::<Vec<_>>(), vec![2, 3]);
+
+        let drifts = drift_report(&settings, &prices, &investments);
+        assert_eq!(drifts, vec![
+            Drift::new(0.75, 0.75, 375_00, 360_00.0 / 487_00.0, 15_00f64.powi(2)),
+            Drift::new(0.25, 0.25, 125_00, 127_00.0 / 487_00.0, 2_00f64.powi(2)),
+        ]);
+        assert_eq!(left_over_cash(100_00, &investments, &prices), 13_00);
+    }
+
+    #[test]
+    fn test_drift_report_after_sells() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 300_00).with_shares(30.0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 100_00).with_shares(10.0),
+        ]);
+        let prices = vec![10_00f64, 10_00f64];
+        let investments = rebalance_investments(Rebalancing::new(5_00, Fee::new(1_00, 0)), Allocation::WholeShares, settings.clone(), &prices);
+
+        // The solver got 280_00 and 100_00 held and a budget of 119_00, what the sell brought in after its fee.
+        let drifts = drift_report(&settings, &prices, &investments);
+        assert_eq!(drifts.iter().map(|drift| drift.target).collect::<Vec<_>>(), vec![280_00, 219_00]);
+        assert_eq!(drifts.iter().map(|drift| drift.current_proportion).collect::<Vec<_>>(), vec![0.75, 0.25]);
+        assert_eq!(drifts.iter().map(|drift| drift.residual_error).collect::<Vec<_>>(), vec![0.0, 9_00f64.powi(2)]);
+    }
+
+    #[test]
+    fn test_left_over_cash_with_fees_and_sells() {
+        let investments = vec![
+            Investment { fee: 1_00, ..Investment::new("ID1".into(), "".to_string(), 0, 10_00) },
+            Investment { fee: 1_00, ..Investment::new("ID2".into(), "".to_string(), 11, 10_00) },
+            Investment { fee: 1_00, action: Action::Sell, ..Investment::new("ID1".into(), "".to_string(), 2, 10_00) },
+        ];
+        assert_eq!(left_over_cash(100_00, &investments, &[10_00f64, 10_00f64]), 7_00);
+    }
+}
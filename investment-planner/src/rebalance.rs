@@ -0,0 +1,54 @@
+use derive_new::new;
+use crate::{next_investments_with, Action, Allocation, Fee, Investment, Settings};
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Hash, new)]
+pub struct Rebalancing {
+    // The most that may be sold at once, in basis points of the current holdings.
+    pub max_turnover_basis_points: i64,
+    // What the broker charges for a sell order, taken from what it brings in.
+    pub sell_fee: Fee,
+}
+
+// Sells whole shares of the etfs above their ideal proportion, down to at most their ideal amount and no more
+// than the shares held, and then buys with the budget plus what the sells brought in. When the sells together would exceed the turnover
+// limit they are all scaled down by the same factor, and a sell that brings in nothing after its fee is left
+// out. The buys come first, one per etf as with next_investments, followed by the sells.
+pub fn rebalance_investments(rebalancing: Rebalancing, allocation: Allocation, mut settings: Settings, prices: &[f64]) -> Vec<Investment> {
+    assert!(prices.iter().all(|&p| p > 0.0));
+    let holdings: i64 = settings.etf_settings.iter().map(|etf| etf.cumulative).sum();
+    let total_proportion: f64 = settings.etf_settings.iter().map(|etf| etf.ideal_proportion).sum();
+    if holdings <= 0 || total_proportion <= 0.0 {
+        return next_investments_with(allocation, settings, prices);
+    }
+
+    let total_amount = (holdings + settings.budget) as f64;
+    let excesses = settings.etf_settings.iter()
+        .map(|etf| (etf.cumulative as f64 - etf.ideal_proportion / total_proportion * total_amount).max(0.0))
+        .collect::<Vec<_>>();
+    let max_turnover = holdings as f64 * rebalancing.max_turnover_basis_points.max(0) as f64 / 10_000.0;
+    let total_excess: f64 = excesses.iter().sum();
+    let scale = if total_excess > max_turnover { max_turnover / total_excess } else { 1.0 };
+
+    let mut sells = vec![];
+    for ((etf, excess), &price) in settings.etf_settings.iter_mut().zip(excesses).zip(prices) {
+        // Truncated to the cent like the prices of the buys.
+        let price = price as i64;
+        if price <= 0 {
+            continue;
+        }
+        let quantity = ((excess * scale / price as f64).floor() as i64).min(etf.shares.floor() as i64);
+        let amount = quantity * price;
+        let fee = rebalancing.sell_fee.of(amount);
+        if quantity <= 0 || amount <= fee {
+            continue;
+        }
+
+        etf.cumulative -= amount;
+        settings.budget += amount - fee;
+        sells.push(Investment { fee, action: Action::Sell, ..Investment::new(etf.id.clone(), etf.name.clone(), quantity, price) });
+    }
+
+    let mut investments = next_investments_with(allocation, settings, prices);
+    investments.extend(sells);
+    investments
+}
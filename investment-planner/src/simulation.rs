@@ -0,0 +1,221 @@
+use std::fmt::{self, Display};
+use derive_new::new;
+use crate::{left_over_cash, next_investments_with, total_fees, Action, Allocation, Settings, Valuation};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PricePath {
+    // Each etf starts at its price in cents and grows by its rate every month, 0.01 being 1%.
+    ConstantGrowth { initial_prices: Vec<f64>, monthly_growth: Vec<f64> },
+    // The prices in cents of every etf, one entry per month.
+    Supplied(Vec<Vec<f64>>),
+}
+
+impl PricePath {
+    pub fn prices_at(&self, month: usize) -> Vec<f64> {
+        match self {
+            PricePath::ConstantGrowth { initial_prices, monthly_growth } => initial_prices.iter()
+                .zip(monthly_growth)
+                .map(|(price, growth)| price * (1.0 + growth).powi(month as i32))
+                .collect(),
+            PricePath::Supplied(prices) => prices[month].clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimulationError {
+    // The supplied price path ends before the month.
+    MissingPrices(usize),
+    // The month does not have one positive price per etf, e.g. after a growth of -100% or less.
+    InvalidPrices(usize),
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SimulationError::MissingPrices(month) => write!(f, "no prices for month {month}"),
+            SimulationError::InvalidPrices(month) => write!(f, "the prices of month {month} are not one positive price per etf"),
+        }
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+#[derive(Debug, Clone, PartialEq, new)]
+#[allow(clippy::too_many_arguments)]
+pub struct SimulatedMonth {
+    pub month: usize,
+    pub prices: Vec<f64>,
+    pub shares: Vec<f64>,
+    // What the shares of each etf are worth at the month's prices, in cents.
+    pub values: Vec<f64>,
+    // The budget left over after the month's investments, carried into the next month.
+    pub cash: i64,
+    // All budgets so far and all fees paid so far.
+    pub contributed: i64,
+    pub fees: i64,
+    // The share of the portfolio sitting in cash.
+    pub cash_drag: f64,
+    // How far the proportions of the values are from the ideal ones, as the root of the summed squared differences.
+    pub tracking_error: f64,
+}
+
+// Invests the budget of the settings every month, starting from their shares and cumulative amounts.
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct Simulation {
+    pub settings: Settings,
+    pub prices: PricePath,
+    pub months: usize,
+    #[new(default)]
+    pub allocation: Allocation,
+    #[new(default)]
+    pub valuation: Valuation,
+}
+
+impl Simulation {
+    pub fn with_allocation(self, allocation: Allocation) -> Self {
+        Self { allocation, ..self }
+    }
+
+    pub fn with_valuation(self, valuation: Valuation) -> Self {
+        Self { valuation, ..self }
+    }
+
+    // The prices of every month rounded to the cent, checked before the planner sees any of them. The planner
+    // and the cash that is carried over both use these, so no month spends more than it has.
+    fn monthly_prices(&self) -> Result<Vec<Vec<f64>>, SimulationError> {
+        (0..self.months).map(|month| {
+            if let PricePath::Supplied(prices) = &self.prices {
+                if month >= prices.len() {
+                    return Err(SimulationError::MissingPrices(month));
+                }
+            }
+            let prices = self.prices.prices_at(month).into_iter().map(f64::round).collect::<Vec<_>>();
+            if prices.len() != self.settings.etf_settings.len() || !prices.iter().all(|&price| price.is_finite() && price > 0.0) {
+                return Err(SimulationError::InvalidPrices(month));
+            }
+            Ok(prices)
+        }).collect()
+    }
+
+    // Runs the planner once a month, with what it left over added to the next month's budget. A supplied
+    // price path has to cover every month.
+    pub fn run(&self) -> Result<Vec<SimulatedMonth>, SimulationError> {
+        let monthly_prices = self.monthly_prices()?;
+        let monthly_budget = self.settings.budget;
+        let total_proportion: f64 = self.settings.etf_settings.iter().map(|etf| etf.ideal_proportion).sum();
+        let ideal = self.settings.etf_settings.iter()
+            .map(|etf| if total_proportion > 0.0 { etf.ideal_proportion / total_proportion } else { 0.0 })
+            .collect::<Vec<_>>();
+
+        let mut settings = self.settings.clone();
+        let mut cash = 0;
+        let mut contributed = 0;
+        let mut fees = 0;
+        let mut months = vec![];
+        for (month, prices) in monthly_prices.into_iter().enumerate() {
+            settings.budget = monthly_budget + cash;
+            contributed += monthly_budget;
+
+            let investments = next_investments_with(self.allocation, settings.clone().valued_at(self.valuation, &prices), &prices);
+            cash = left_over_cash(settings.budget, &investments, &prices);
+            fees += total_fees(&investments);
+            for investment in investments.iter().filter(|investment| investment.action == Action::Buy) {
+                let etf = settings.etf_settings.iter_mut()
+                    .find(|etf| etf.id == investment.etf_id)
+                    .expect("investments are in the simulated etfs");
+                etf.shares += investment.units();
+                etf.cumulative += investment.amount();
+            }
+
+            let shares = settings.etf_settings.iter().map(|etf| etf.shares).collect::<Vec<_>>();
+            let values = shares.iter().zip(&prices).map(|(shares, price)| shares * price).collect::<Vec<_>>();
+            let total_value: f64 = values.iter().sum();
+            let cash_drag = if total_value + cash as f64 > 0.0 { cash as f64 / (total_value + cash as f64) } else { 0.0 };
+            let tracking_error = values.iter().zip(&ideal)
+                .map(|(value, ideal)| {
+                    let proportion = if total_value > 0.0 { value / total_value } else { 0.0 };
+                    (proportion - ideal).powi(2)
+                })
+                .sum::<f64>()
+                .sqrt();
+
+            months.push(SimulatedMonth::new(month, prices, shares, values, cash, contributed, fees, cash_drag, tracking_error));
+        }
+        Ok(months)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EtfSetting;
+
+    #[test]
+    fn test_leftover_cash_is_carried_over() {
+        let settings = Settings::new(25_00, vec![EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 1.0, 0)]);
+        let prices = PricePath::ConstantGrowth { initial_prices: vec![10_00.0], monthly_growth: vec![0.0] };
+        let months = Simulation::new(settings, prices, 3).run().unwrap();
+
+        assert_eq!(months.iter().map(|m| (m.shares[0], m.cash, m.contributed)).collect::<Vec<_>>(), vec![
+            (2.0, 5_00, 25_00),
+            (5.0, 0, 50_00),
+            (7.0, 5_00, 75_00),
+        ]);
+        assert_eq!(months[0].cash_drag, 0.2);
+        assert!(months.iter().all(|m| m.tracking_error == 0.0));
+    }
+
+    #[test]
+    fn test_constant_growth() {
+        let prices = PricePath::ConstantGrowth { initial_prices: vec![10_00.0, 20_00.0], monthly_growth: vec![0.01, 0.0] };
+        assert_eq!(prices.prices_at(0), vec![10_00.0, 20_00.0]);
+        assert_eq!(prices.prices_at(2), vec![10_00.0 * 1.01 * 1.01, 20_00.0]);
+    }
+
+    #[test]
+    fn test_market_value_tracks_ideal_proportions_closer() {
+        let settings = Settings::new(100_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.5, 0),
+        ]);
+        // ID1 doubles after the first month.
+        let prices = PricePath::Supplied(vec![vec![10_00.0, 10_00.0], vec![20_00.0, 10_00.0], vec![20_00.0, 10_00.0]]);
+        let simulation = Simulation::new(settings, prices, 3);
+
+        let at_cost = simulation.clone().run().unwrap();
+        let at_market = simulation.with_valuation(Valuation::MarketValue).run().unwrap();
+
+        assert_eq!(at_cost[0], at_market[0]);
+        assert!(at_market[2].tracking_error < at_cost[2].tracking_error);
+        assert_eq!(at_market[2].values.iter().sum::<f64>() + at_market[2].cash as f64, 350_00.0);
+    }
+
+    #[test]
+    fn test_invalid_price_paths() {
+        let settings = Settings::new(100_00, vec![EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 1.0, 0)]);
+
+        let short = PricePath::Supplied(vec![vec![10_00.0], vec![10_00.0]]);
+        assert_eq!(Simulation::new(settings.clone(), short, 3).run(), Err(SimulationError::MissingPrices(2)));
+
+        let wiped_out = PricePath::ConstantGrowth { initial_prices: vec![10_00.0], monthly_growth: vec![-1.0] };
+        assert_eq!(Simulation::new(settings.clone(), wiped_out, 3).run(), Err(SimulationError::InvalidPrices(1)));
+
+        let missing_etf = PricePath::ConstantGrowth { initial_prices: vec![], monthly_growth: vec![] };
+        assert_eq!(Simulation::new(settings, missing_etf, 3).run(), Err(SimulationError::InvalidPrices(0)));
+    }
+
+    #[test]
+    fn test_cash_is_never_negative_with_prices_between_cents() {
+        let settings = Settings::new(500_00, vec![
+            EtfSetting::new("ID1".into(), "".to_string(), "".to_string(), 0.5, 0),
+            EtfSetting::new("ID2".into(), "".to_string(), "".to_string(), 0.3, 0),
+            EtfSetting::new("ID3".into(), "".to_string(), "".to_string(), 0.2, 0),
+        ]);
+        let prices = PricePath::ConstantGrowth { initial_prices: vec![112_36.4, 48_70.6, 7_77.7], monthly_growth: vec![0.0049, 0.0031, -0.0017] };
+        for allocation in [Allocation::WholeShares, Allocation::Fractional { decimals: 3 }] {
+            let months = Simulation::new(settings.clone(), prices.clone(), 24).with_allocation(allocation).run().unwrap();
+            assert!(months.iter().all(|month| month.cash >= 0), "{allocation:?}: {:?}", months.iter().map(|m| m.cash).collect::<Vec<_>>());
+        }
+    }
+}
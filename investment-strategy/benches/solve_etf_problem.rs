@@ -0,0 +1,30 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use investment_strategy::{solve_etf_problem, EtfItem};
+use std::hint::black_box;
+
+// Prices in cents of cheap and expensive etfs, with the ideal proportion of each.
+const ETFS: [(i64, f64); 5] = [(103, 0.1), (777, 0.3), (5_501, 0.3), (9_845, 0.2), (45_013, 0.1)];
+
+fn etf_items(budget: i64, cumulative: i64) -> Vec<EtfItem> {
+    let total = (budget + cumulative * ETFS.len() as i64) as f64;
+    ETFS.iter()
+        .map(|&(price, proportion)| EtfItem::new(cumulative, cumulative.max((proportion * total) as i64), price))
+        .collect()
+}
+
+fn bench_solve_etf_problem(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solve_etf_problem");
+    // From a monthly plan of 500 euros up to a lump sum of 50,000 and 500,000 euros, in cents.
+    for budget in [50_000, 500_000, 5_000_000, 50_000_000] {
+        group.bench_with_input(BenchmarkId::new("empty_portfolio", budget), &budget, |b, &budget| {
+            b.iter(|| solve_etf_problem(black_box(budget), etf_items(budget, 0)))
+        });
+        group.bench_with_input(BenchmarkId::new("existing_portfolio", budget), &budget, |b, &budget| {
+            b.iter(|| solve_etf_problem(black_box(budget), etf_items(budget, 2_000_000)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_solve_etf_problem);
+criterion_main!(benches);
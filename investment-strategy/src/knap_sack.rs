@@ -1,9 +1,242 @@
+#[cfg(test)]
 use std::rc::Rc;
+#[cfg(test)]
 use derive_new::new;
 
+#[cfg(test)]
 use crate::rclist::RcList;
 use crate::EtfItem;
 
+// Stands in for an unreachable state, far enough from i64::MAX that adding a cost cannot overflow.
+const UNREACHABLE: i64 = i64::MAX / 4;
+
+fn squared_error(etf: &EtfItem, quantity: i64) -> i64 {
+    (etf.target - (etf.cumulative + etf.price * quantity)).pow(2)
+}
+
+// The largest quantity that still brings the etf strictly closer to its target, i.e. the number of
+// items `generate_weights_and_values` emits for it, given that every unit takes `weight` of the budget.
+fn max_useful_quantity(budget: i64, etf: &EtfItem, weight: i64) -> i64 {
+    let distance = etf.target - etf.cumulative;
+    if etf.price <= 0 || distance <= 0 || budget <= 0 {
+        return 0;
+    }
+    ((2 * distance + etf.price - 1) / (2 * etf.price)).min(budget / weight)
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+// Computes next[x] = min over units u <= max_units of previous[x - u * step] + cost(u), for a convex cost.
+// Within one residue class modulo step this is a min-plus convolution with a convex sequence, whose
+// optimal arguments are monotone, so each class is solved by divide and conquer in O(n log n).
+fn convolve_convex(previous: &[i64], step: usize, max_units: usize, cost: &impl Fn(usize) -> i64) -> (Vec<i64>, Vec<usize>) {
+    let mut next = vec![UNREACHABLE; previous.len()];
+    let mut units = vec![0; previous.len()];
+
+    for residue in 0..step.min(previous.len()) {
+        let class = (residue..previous.len()).step_by(step).map(|x| previous[x]).collect::<Vec<_>>();
+        let mut best = vec![(UNREACHABLE, 0); class.len()];
+        convolve_class(&class, max_units, cost, 0, class.len() - 1, 0, class.len() - 1, &mut best);
+
+        for (k, (value, unit)) in best.into_iter().enumerate() {
+            next[residue + k * step] = value.min(UNREACHABLE);
+            units[residue + k * step] = unit;
+        }
+    }
+    (next, units)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn convolve_class(class: &[i64], max_units: usize, cost: &impl Fn(usize) -> i64, lo: usize, hi: usize, opt_lo: usize, opt_hi: usize, best: &mut [(i64, usize)]) {
+    let mid = lo + (hi - lo) / 2;
+    let first_j = opt_lo.max(mid.saturating_sub(max_units));
+    let (best_j, best_value) = class[first_j..=opt_hi.min(mid)].iter()
+        .enumerate()
+        .map(|(offset, &value)| (first_j + offset, value + cost(mid - first_j - offset)))
+        .min_by_key(|&(j, value)| (value, j))
+        .expect("the search range is never empty");
+    best[mid] = (best_value, mid - best_j);
+
+    if mid > lo {
+        convolve_class(class, max_units, cost, lo, mid - 1, opt_lo, best_j, best);
+    }
+    if mid < hi {
+        convolve_class(class, max_units, cost, mid + 1, hi, best_j, opt_hi, best);
+    }
+}
+
+// An etf as the dynamic program sees it, in lots rather than units. Buying nothing is always allowed,
+// any other order pays the fixed fee and has between min_lots and max_lots lots. Past the first allowed
+// order the error is convex in the lots again, so the fee and the minimum only add one jump from
+// nothing to the first allowed order.
+struct Orderable<'a> {
+    etf: &'a EtfItem,
+    lot_size: i64,
+    lot_weight: i64,
+    fee: i64,
+    min_lots: i64,
+    // 0 when no order fits the budget and the limits, or when any order would be worse than none.
+    max_lots: i64,
+}
+
+impl Orderable<'_> {
+    fn new(budget: i64, etf: &EtfItem) -> Orderable<'_> {
+        let lot_size = etf.limits.lot_size();
+        let lot_weight = lot_size * (etf.price + etf.fee.per_unit(etf.price));
+        let min_lots = etf.limits.min_lots(etf.price);
+        let mut orderable = Orderable { etf, lot_size, lot_weight, fee: etf.fee.fixed, min_lots, max_lots: 0 };
+        if etf.price <= 0 {
+            return orderable;
+        }
+
+        let lot_item = EtfItem::new(etf.cumulative, etf.target, lot_size * etf.price);
+        let useful = max_useful_quantity(budget - orderable.fee, &lot_item, lot_weight);
+        let affordable = (budget - orderable.fee).max(0) / lot_weight;
+        let max_lots = useful.max(min_lots).min(affordable);
+        let max_lots = etf.limits.max_lots().map_or(max_lots, |limit| max_lots.min(limit));
+        if max_lots >= min_lots && (useful >= min_lots || orderable.error(min_lots) < orderable.error(0)) {
+            orderable.max_lots = max_lots;
+        }
+        orderable
+    }
+
+    fn error(&self, lots: i64) -> i64 {
+        squared_error(self.etf, lots * self.lot_size)
+    }
+
+    // The budget the most useful order takes, fee included.
+    fn max_spent(&self) -> i64 {
+        if self.max_lots > 0 { self.fee + self.max_lots * self.lot_weight } else { 0 }
+    }
+}
+
+pub fn knap_sack_quantities(budget: i64, etfs: &[EtfItem]) -> Vec<i64> {
+    let orderables = etfs.iter().map(|etf| Orderable::new(budget, etf)).collect::<Vec<_>>();
+    lots_within(budget, &orderables).into_iter()
+        .zip(&orderables)
+        .map(|(lots, orderable)| lots * orderable.lot_size)
+        .collect()
+}
+
+// Solves the same problem as `knap_sack_rc_list` over `generate_weights_and_values`, without one item
+// per unit bought. Every etf contributes between 0 and its maximum useful lots, and the dynamic program
+// runs over whichever is smaller: the budget, or the amount by which buying every etf up to its maximum
+// useful lots would exceed the budget. Targets from the planner sum to the budget, so the latter is
+// below the sum of the prices and fees, however large the budget is.
+fn lots_within(budget: i64, orderables: &[Orderable]) -> Vec<i64> {
+    let max_lots = orderables.iter().map(|orderable| orderable.max_lots).collect::<Vec<_>>();
+    let max_spent: i64 = orderables.iter().map(Orderable::max_spent).sum();
+    if max_spent <= budget {
+        return max_lots;
+    }
+
+    // Every weight and fee is a multiple of the scale, so one cell of the dynamic program is one scale.
+    let scale = orderables.iter()
+        .filter(|orderable| orderable.max_lots > 0)
+        .fold(0, |scale, orderable| gcd(orderable.fee, gcd(orderable.lot_weight, scale)));
+    if budget <= 0 || scale == 0 {
+        return vec![0; orderables.len()];
+    }
+    let capacity = (budget / scale) as usize;
+    let excess = (max_spent - budget + scale - 1) / scale;
+
+    if (excess as usize) < capacity {
+        let removed = cover_excess(excess as usize, orderables, scale);
+        max_lots.iter().zip(removed).map(|(max_lots, removed)| max_lots - removed).collect()
+    } else {
+        fill_capacity(capacity, orderables, scale)
+    }
+}
+
+// Chooses how many lots to buy of each etf, minimizing the total error while spending at most the capacity.
+fn fill_capacity(capacity: usize, orderables: &[Orderable], scale: i64) -> Vec<i64> {
+    // best[x] is the smallest increase over the error of buying nothing that spends at most x.
+    let mut best = vec![0; capacity + 1];
+    let mut choices = vec![];
+    for orderable in orderables {
+        let mut units = vec![0; capacity + 1];
+        if orderable.max_lots > 0 {
+            // The order pays the fee and the minimum, then a convex choice of further lots.
+            let step = (orderable.lot_weight / scale) as usize;
+            let up_front = (orderable.fee / scale) as usize + orderable.min_lots as usize * step;
+            let cost = |extra: usize| orderable.error(orderable.min_lots + extra as i64) - orderable.error(0);
+            let (ordered, extra) = convolve_convex(&best, step, (orderable.max_lots - orderable.min_lots) as usize, &cost);
+            for x in up_front..=capacity {
+                if ordered[x - up_front] < best[x] {
+                    best[x] = ordered[x - up_front];
+                    units[x] = orderable.min_lots as usize + extra[x - up_front];
+                }
+            }
+        }
+        choices.push(units);
+    }
+
+    let mut lots = vec![0; orderables.len()];
+    let mut spent = (0..=capacity).min_by_key(|&w| (best[w], w)).unwrap_or(0);
+    for (i, orderable) in orderables.iter().enumerate().rev() {
+        lots[i] = choices[i][spent] as i64;
+        if lots[i] > 0 {
+            spent -= (orderable.fee / scale) as usize + choices[i][spent] * (orderable.lot_weight / scale) as usize;
+        }
+    }
+    lots
+}
+
+// Chooses how many lots to remove from each etf's maximum useful lots, minimizing the total loss while
+// freeing at least the excess. An etf either keeps at least its minimum, or removes every lot and frees
+// its fee as well. On ties the later etfs give up their lots, like the convolution prefers.
+fn cover_excess(excess: usize, orderables: &[Orderable], scale: i64) -> Vec<i64> {
+    // best[c] is the smallest loss that frees at least c, so best[0] is always 0.
+    let mut best = vec![UNREACHABLE; excess + 1];
+    best[0] = 0;
+    let mut choices = vec![];
+    for orderable in orderables {
+        if orderable.max_lots == 0 {
+            choices.push(vec![0; excess + 1]);
+            continue;
+        }
+        let step = (orderable.lot_weight / scale) as usize;
+        let max_units = (orderable.max_lots - orderable.min_lots) as usize;
+        let loss = |removed: usize| orderable.error(orderable.max_lots - removed as i64) - orderable.error(orderable.max_lots);
+        let (mut next, mut units) = convolve_convex(&best, step, max_units, &loss);
+
+        // Removing more lots than needed to reach `c` frees everything at once.
+        for c in 1..=excess {
+            let enough = c.div_ceil(step);
+            if enough <= max_units && loss(enough) <= next[c] {
+                next[c] = loss(enough);
+                units[c] = enough;
+            }
+        }
+        let all = (orderable.max_spent() / scale) as usize;
+        let loss_all = orderable.error(0) - orderable.error(orderable.max_lots);
+        for c in 0..=excess {
+            if best[c.saturating_sub(all)] + loss_all <= next[c] {
+                next[c] = best[c.saturating_sub(all)] + loss_all;
+                units[c] = orderable.max_lots as usize;
+            }
+        }
+        best = next;
+        choices.push(units);
+    }
+
+    let mut removed = vec![0; orderables.len()];
+    let mut freed = excess;
+    for (i, orderable) in orderables.iter().enumerate().rev() {
+        removed[i] = choices[i][freed] as i64;
+        let freed_by_removal = if removed[i] > 0 && removed[i] == orderable.max_lots {
+            (orderable.max_spent() / scale) as usize
+        } else {
+            choices[i][freed] * (orderable.lot_weight / scale) as usize
+        };
+        freed = freed.saturating_sub(freed_by_removal);
+    }
+    removed
+}
+
+#[cfg(test)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, new)]
 pub struct KnapSackItem {
     pub value: i64,
@@ -12,21 +245,26 @@ pub struct KnapSackItem {
     pub etf_index: usize,
 }
 
+#[cfg(test)]
 pub fn generate_weights_and_values(budget: i64, etfs: &[EtfItem]) -> Vec<KnapSackItem> {
     let mut items = vec![];
 
     for (etf_index, etf) in etfs.iter().enumerate() {
+        // One item per lot. Minimum orders cannot be expressed with independent items, the solver
+        // handles those by buying the minimum up front.
+        let lot_price = etf.price * etf.limits.lot_size();
+        let max_lots = etf.limits.max_lots().unwrap_or(i64::MAX);
         let mut buy_quantity = 1;
         let mut last_error = (etf.target - etf.cumulative).pow(2);
-        while etf.price * buy_quantity <= budget {
-            let amount = etf.cumulative + (etf.price * buy_quantity);
+        while lot_price * buy_quantity <= budget && buy_quantity <= max_lots {
+            let amount = etf.cumulative + (lot_price * buy_quantity);
             let error = (etf.target - amount).pow(2);
             let value = last_error - error;
             if value <= 0 {
                 break;
             }
 
-            items.push(KnapSackItem::new(value, etf.price, etf_index));
+            items.push(KnapSackItem::new(value, lot_price, etf_index));
 
             last_error = error;
             buy_quantity += 1;
@@ -36,6 +274,9 @@ pub fn generate_weights_and_values(budget: i64, etfs: &[EtfItem]) -> Vec<KnapSac
     items
 }
 
+// One item per unit bought and one cell per cent of budget: only usable for small budgets, and kept as
+// the reference the scalable solver is tested against.
+#[cfg(test)]
 pub fn knap_sack_rc_list(max_weight: i64, weights: &[i64], values: &[i64]) -> (i64, Vec<usize>) {
     let max_weight = max_weight as usize;
     let weights = weights.iter().map(|w| *w as usize).collect::<Vec<_>>();
@@ -1,28 +1,122 @@
+#[cfg(test)]
 mod rclist;
 mod knap_sack;
+mod milp;
 
 use derive_new::new;
-use knap_sack::{knap_sack_rc_list, generate_weights_and_values};
+pub use good_lp::ResolutionError;
+use knap_sack::knap_sack_quantities;
+
+// What the broker charges per order: a fixed amount plus a percentage of the amount bought, both in cents.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, PartialOrd, Ord, Hash, new)]
+pub struct Fee {
+    pub fixed: i64,
+    // Hundredths of a percent of the amount bought, rounded up to the cent.
+    pub basis_points: i64,
+}
+
+impl Fee {
+    pub fn of(&self, amount: i64) -> i64 {
+        if amount <= 0 {
+            return 0;
+        }
+        self.fixed + (amount * self.basis_points + 9_999) / 10_000
+    }
+
+    // The percentage on a single unit, rounded up. Rounding up per unit never charges less than rounding
+    // up the whole order, so the solvers budget it per unit and stay linear in the quantity.
+    pub fn per_unit(&self, price: i64) -> i64 {
+        (price * self.basis_points + 9_999) / 10_000
+    }
+}
+
+// Restrictions on a single order. Buying nothing is always allowed, any other quantity has to be a
+// multiple of the lot size, at least the minimum quantity and amount and at most the maximum quantity.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Hash, new)]
+pub struct OrderLimits {
+    pub min_quantity: i64,
+    pub max_quantity: Option<i64>,
+    pub lot_size: i64,
+    // In cents, like the price.
+    pub min_amount: i64,
+}
+
+impl Default for OrderLimits {
+    fn default() -> Self {
+        OrderLimits::new(0, None, 1, 0)
+    }
+}
+
+impl OrderLimits {
+    pub fn allows(&self, quantity: i64, price: i64) -> bool {
+        quantity == 0 || (quantity % self.lot_size() == 0
+            && quantity >= self.min_quantity
+            && self.max_quantity.is_none_or(|max_quantity| quantity <= max_quantity)
+            && quantity * price >= self.min_amount)
+    }
+
+    pub fn lot_size(&self) -> i64 {
+        self.lot_size.max(1)
+    }
+
+    // The fewest lots an order can have, at least one.
+    fn min_lots(&self, price: i64) -> i64 {
+        let min_for_amount = if price > 0 { (self.min_amount + price - 1) / price } else { 0 };
+        let min_quantity = self.min_quantity.max(min_for_amount).max(1);
+        (min_quantity + self.lot_size() - 1) / self.lot_size()
+    }
+
+    fn max_lots(&self) -> Option<i64> {
+        self.max_quantity.map(|max_quantity| max_quantity.max(0) / self.lot_size())
+    }
+}
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, new)]
 pub struct EtfItem {
     pub cumulative: i64,
     pub target: i64,
     pub price: i64,
+    #[new(default)]
+    pub fee: Fee,
+    #[new(default)]
+    pub limits: OrderLimits,
 }
 
+impl EtfItem {
+    pub fn with_fee(self, fee: Fee) -> Self {
+        Self { fee, ..self }
+    }
+
+    pub fn with_limits(self, limits: OrderLimits) -> Self {
+        Self { limits, ..self }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum Solver {
+    #[default]
+    KnapSack,
+    Milp,
+}
+
+// With the default solver, the knapsack, which cannot fail.
 pub fn solve_etf_problem(budget: i64, etfs: Vec<EtfItem>) -> Vec<(EtfItem, i64)> {
-    let items = generate_weights_and_values(budget, &etfs);
-    let weights = items.iter().map(|item| item.weight).collect::<Vec<_>>();
-    let values = items.iter().map(|item| item.value).collect::<Vec<_>>();
+    solve_knap_sack(budget, etfs)
+}
 
-    let mut buy_quantities = vec![0i64; etfs.len()];
-    let (_, item_indices) = knap_sack_rc_list(budget, &weights, &values);
-    for item_index in item_indices {
-        let item = items[item_index];
-        buy_quantities[item.etf_index] += 1;
+// Buying nothing is always feasible, but the milp solver can still fail numerically.
+pub fn solve_etf_problem_with(solver: Solver, budget: i64, etfs: Vec<EtfItem>) -> Result<Vec<(EtfItem, i64)>, ResolutionError> {
+    match solver {
+        Solver::KnapSack => Ok(solve_knap_sack(budget, etfs)),
+        Solver::Milp => {
+            let buy_quantities = milp::solve_milp(budget, &etfs)?;
+            Ok(etfs.into_iter().zip(buy_quantities).collect())
+        }
     }
+}
 
+fn solve_knap_sack(budget: i64, etfs: Vec<EtfItem>) -> Vec<(EtfItem, i64)> {
+    let buy_quantities = knap_sack_quantities(budget, &etfs);
     etfs.into_iter().zip(buy_quantities).collect()
 }
 
@@ -30,9 +124,15 @@ pub fn calc_total_price(etfs: &[EtfItem], buy_quantities: &[i64]) -> i64 {
     etfs.iter().zip(buy_quantities).map(|(etf, quantity)| etf.price * quantity).sum()
 }
 
+pub fn calc_total_fees(etfs: &[EtfItem], buy_quantities: &[i64]) -> i64 {
+    etfs.iter().zip(buy_quantities).map(|(etf, quantity)| etf.fee.of(etf.price * quantity)).sum()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use knap_sack::{knap_sack_rc_list, generate_weights_and_values};
+    use proptest::prelude::*;
 
     fn calc_total_error(etfs: &[EtfItem], buy_quantities: &[i64]) -> i64 {
         etfs.iter().zip(buy_quantities).map(|(etf, quantity)| {
@@ -257,4 +357,219 @@ mod tests {
         assert_eq!(max_value, 0);
         assert!(selected_items.is_empty());
     }
+
+    fn solve_with_items(budget: i64, etfs: &[EtfItem]) -> Vec<i64> {
+        let items = generate_weights_and_values(budget, etfs);
+        let weights = items.iter().map(|item| item.weight).collect::<Vec<_>>();
+        let values = items.iter().map(|item| item.value).collect::<Vec<_>>();
+
+        let mut buy_quantities = vec![0i64; etfs.len()];
+        let (_, item_indices) = knap_sack_rc_list(budget, &weights, &values);
+        for item_index in item_indices {
+            let etf_index = items[item_index].etf_index;
+            buy_quantities[etf_index] += etfs[etf_index].limits.lot_size();
+        }
+        buy_quantities
+    }
+
+    #[test]
+    fn test_solve_etf_problem_large_budget() {
+        let budget = 5_000_000;
+        let etfs = vec![
+            EtfItem::new(0, 2_000_000, 1_03),
+            EtfItem::new(0, 1_500_000, 7_77),
+            EtfItem::new(0, 1_000_000, 55_01),
+            EtfItem::new(0, 500_000, 450_13),
+        ];
+        let buy_quantities = solve_etf_problem(budget, etfs.clone()).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+        assert!(calc_total_price(&etfs, &buy_quantities) <= budget);
+        assert!(calc_total_error(&etfs, &buy_quantities) <= calc_total_error(&etfs, &[19417, 1930, 181, 11]));
+    }
+
+    #[test]
+    fn test_fee_of_order() {
+        let fee = Fee::new(2_00, 25);
+        assert_eq!(fee.of(0), 0);
+        assert_eq!(fee.of(100_00), 2_25);
+        assert_eq!(fee.of(1), 2_01);
+        assert_eq!(fee.per_unit(4_87), 2);
+    }
+
+    #[test]
+    fn test_fixed_fee_concentrates_orders() {
+        let fee = Fee::new(4_00, 0);
+        let etfs = vec![
+            EtfItem::new(0, 5_00, 1_00).with_fee(fee),
+            EtfItem::new(0, 5_00, 1_00).with_fee(fee),
+        ];
+        // Without fees both etfs get half of the budget, with them a single order wastes less.
+        let without_fees = solve_etf_problem(10_00, etfs.iter().map(|etf| etf.with_fee(Fee::default())).collect());
+        assert_eq!(without_fees.iter().map(|(_, q)| *q).collect::<Vec<_>>(), vec![5, 5]);
+
+        let quantities = solve_etf_problem(10_00, etfs.clone()).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+        assert_eq!(quantities, vec![5, 0]);
+        assert_eq!(calc_total_price(&etfs, &quantities) + calc_total_fees(&etfs, &quantities), 9_00);
+    }
+
+    #[test]
+    fn test_fees_larger_than_budget_buy_nothing() {
+        let etfs = vec![EtfItem::new(0, 5_00, 1_00).with_fee(Fee::new(6_00, 0))];
+        assert_eq!(solve_etf_problem(5_00, etfs), vec![(EtfItem::new(0, 5_00, 1_00).with_fee(Fee::new(6_00, 0)), 0)]);
+    }
+
+    #[test]
+    fn test_no_budget_buys_nothing() {
+        let etfs = vec![EtfItem::new(0, 5_00, 1_00), EtfItem::new(0, 5_00, 2_00).with_fee(Fee::new(1_00, 0))];
+        for budget in [0, -3_00] {
+            let quantities = solve_etf_problem(budget, etfs.clone()).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+            assert_eq!(quantities, vec![0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_many_etfs_with_fees_and_minimums() {
+        // Deciding which of them get an order cannot go through every combination of them.
+        let etfs = (0..70)
+            .map(|i| EtfItem::new(0, 50_00, 1_00 + i).with_fee(Fee::new(1_00, 0)).with_limits(OrderLimits::new(0, None, 1, 20_00)))
+            .collect::<Vec<_>>();
+        let quantities = solve_etf_problem(500_00, etfs.clone()).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+
+        assert!(etfs.iter().zip(&quantities).all(|(etf, &quantity)| etf.limits.allows(quantity, etf.price)));
+        assert!(calc_total_price(&etfs, &quantities) + calc_total_fees(&etfs, &quantities) <= 500_00);
+        assert_eq!(quantities.iter().filter(|&&quantity| quantity > 0).count(), 23);
+    }
+
+    // Tries every combination of quantities, for the few etfs and small budgets of the property tests.
+    fn solve_by_brute_force(budget: i64, etfs: &[EtfItem]) -> Vec<i64> {
+        let unit_costs = etfs.iter().map(|etf| etf.price + etf.fee.per_unit(etf.price)).collect::<Vec<_>>();
+        let mut best = (i64::MAX, vec![0; etfs.len()]);
+        let mut quantities = vec![0; etfs.len()];
+        loop {
+            let cost: i64 = etfs.iter().zip(&unit_costs).zip(&quantities)
+                .map(|((etf, unit_cost), &quantity)| if quantity > 0 { etf.fee.fixed + unit_cost * quantity } else { 0 })
+                .sum();
+            let allowed = etfs.iter().zip(&quantities).all(|(etf, &quantity)| etf.limits.allows(quantity, etf.price));
+            if allowed && cost <= budget && calc_total_error(etfs, &quantities) < best.0 {
+                best = (calc_total_error(etfs, &quantities), quantities.clone());
+            }
+
+            let Some(i) = (0..etfs.len()).find(|&i| (quantities[i] + 1) * unit_costs[i] <= budget) else {
+                return best.1;
+            };
+            quantities[i] += 1;
+            quantities[..i].iter_mut().for_each(|quantity| *quantity = 0);
+        }
+    }
+
+    #[test]
+    fn test_order_limits_allow() {
+        let limits = OrderLimits::new(4, Some(12), 2, 10_00);
+        assert!(limits.allows(0, 1_00));
+        assert!(!limits.allows(2, 1_00));
+        assert!(!limits.allows(5, 3_00));
+        assert!(limits.allows(6, 3_00));
+        assert!(!limits.allows(14, 3_00));
+        assert!(!limits.allows(8, 1_00));
+        assert!(limits.allows(10, 1_00));
+    }
+
+    #[test]
+    fn test_lot_size_and_maximum() {
+        let etfs = vec![
+            EtfItem::new(0, 10_00, 1_00).with_limits(OrderLimits::new(0, None, 4, 0)),
+            EtfItem::new(0, 10_00, 1_00).with_limits(OrderLimits::new(0, Some(3), 1, 0)),
+        ];
+        let quantities = solve_etf_problem(20_00, etfs).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+        assert_eq!(quantities, vec![8, 3]);
+    }
+
+    #[test]
+    fn test_minimum_order_amount() {
+        let etfs = vec![
+            EtfItem::new(0, 2_00, 1_00).with_limits(OrderLimits::new(0, None, 1, 5_00)),
+            EtfItem::new(0, 3_00, 1_00),
+        ];
+        // Buying the minimum of the first overshoots its target by more than buying nothing misses it.
+        let quantities = solve_etf_problem(5_00, etfs).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+        assert_eq!(quantities, vec![0, 3]);
+
+        let etfs = vec![
+            EtfItem::new(0, 4_00, 1_00).with_limits(OrderLimits::new(0, None, 1, 5_00)),
+            EtfItem::new(0, 3_00, 1_00),
+        ];
+        let quantities = solve_etf_problem(8_00, etfs).into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+        assert_eq!(quantities, vec![5, 3]);
+    }
+
+    fn order_limits() -> impl Strategy<Value = OrderLimits> {
+        (0..4i64, prop::option::of(0..8i64), 1..4i64, 0..400i64)
+            .prop_map(|(min_quantity, max_quantity, lot_size, min_amount)| OrderLimits::new(min_quantity, max_quantity, lot_size, min_amount))
+    }
+
+    fn etf_item() -> impl Strategy<Value = EtfItem> {
+        (0..500i64, 0..1500i64, 1..300i64).prop_map(|(cumulative, target, price)| EtfItem::new(cumulative, target, price))
+    }
+
+    proptest! {
+        #[test]
+        fn test_knap_sack_quantities_matches_items(budget in -100..1000i64, etfs in prop::collection::vec(etf_item(), 1..5)) {
+            let reference = solve_with_items(budget.max(0), &etfs);
+            let buy_quantities = knap_sack_quantities(budget, &etfs);
+
+            prop_assert!(calc_total_price(&etfs, &buy_quantities) <= budget.max(0));
+            prop_assert_eq!(calc_total_error(&etfs, &buy_quantities), calc_total_error(&etfs, &reference));
+        }
+
+        #[test]
+        fn test_knap_sack_quantities_with_fees(
+            budget in -100..600i64,
+            etfs in prop::collection::vec((etf_item(), 0..200i64, 0..300i64), 1..4),
+        ) {
+            let etfs = etfs.into_iter().map(|(etf, fixed, basis_points)| etf.with_fee(Fee::new(fixed, basis_points))).collect::<Vec<_>>();
+            let reference = solve_by_brute_force(budget, &etfs);
+            let buy_quantities = knap_sack_quantities(budget, &etfs);
+
+            prop_assert!(calc_total_price(&etfs, &buy_quantities) + calc_total_fees(&etfs, &buy_quantities) <= budget.max(0));
+            prop_assert_eq!(calc_total_error(&etfs, &buy_quantities), calc_total_error(&etfs, &reference));
+        }
+
+        #[test]
+        fn test_knap_sack_quantities_with_limits(
+            budget in -100..600i64,
+            etfs in prop::collection::vec((etf_item(), order_limits(), 0..100i64), 1..4),
+        ) {
+            let etfs = etfs.into_iter().map(|(etf, limits, fixed)| etf.with_limits(limits).with_fee(Fee::new(fixed, 0))).collect::<Vec<_>>();
+            let reference = solve_by_brute_force(budget, &etfs);
+            let buy_quantities = knap_sack_quantities(budget, &etfs);
+
+            prop_assert!(etfs.iter().zip(&buy_quantities).all(|(etf, &quantity)| etf.limits.allows(quantity, etf.price)));
+            prop_assert!(calc_total_price(&etfs, &buy_quantities) + calc_total_fees(&etfs, &buy_quantities) <= budget.max(0));
+            prop_assert_eq!(calc_total_error(&etfs, &buy_quantities), calc_total_error(&etfs, &reference));
+        }
+
+        #[test]
+        fn test_knap_sack_quantities_with_lots_matches_items(
+            budget in -100..1000i64,
+            etfs in prop::collection::vec((etf_item(), prop::option::of(0..8i64), 1..4i64), 1..5),
+        ) {
+            let etfs = etfs.into_iter()
+                .map(|(etf, max_quantity, lot_size)| etf.with_limits(OrderLimits::new(0, max_quantity, lot_size, 0)))
+                .collect::<Vec<_>>();
+            let reference = solve_with_items(budget.max(0), &etfs);
+            let buy_quantities = knap_sack_quantities(budget, &etfs);
+
+            prop_assert!(calc_total_price(&etfs, &buy_quantities) <= budget.max(0));
+            prop_assert_eq!(calc_total_error(&etfs, &buy_quantities), calc_total_error(&etfs, &reference));
+        }
+
+        #[test]
+        fn test_knap_sack_quantities_with_common_divisor(budget in -100..2000i64, etfs in prop::collection::vec(etf_item(), 1..4)) {
+            let etfs = etfs.into_iter().map(|etf| EtfItem::new(etf.cumulative, etf.target, etf.price * 5)).collect::<Vec<_>>();
+            let reference = solve_with_items(budget.max(0), &etfs);
+            let buy_quantities = knap_sack_quantities(budget, &etfs);
+
+            prop_assert!(calc_total_price(&etfs, &buy_quantities) <= budget.max(0));
+            prop_assert_eq!(calc_total_error(&etfs, &buy_quantities), calc_total_error(&etfs, &reference));
+        }
+    }
 }
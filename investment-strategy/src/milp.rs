@@ -0,0 +1,197 @@
+use std::collections::BTreeSet;
+use good_lp::{constraint, default_solver, variable, variables, Expression, ResolutionError, Solution, SolverModel, Variable};
+
+use crate::EtfItem;
+
+fn squared_error(etf: &EtfItem, quantity: i64) -> f64 {
+    ((etf.target - (etf.cumulative + etf.price * quantity)) as f64).powi(2)
+}
+
+// What a lot takes of the budget, including its share of the percentage fee.
+fn lot_cost(etf: &EtfItem) -> i64 {
+    etf.limits.lot_size() * (etf.price + etf.fee.per_unit(etf.price))
+}
+
+fn max_lots(budget: i64, etf: &EtfItem) -> i64 {
+    if etf.price <= 0 {
+        return 0;
+    }
+    let affordable = (budget - etf.fee.fixed).max(0) / lot_cost(etf);
+    etf.limits.max_lots().map_or(affordable, |max_lots| affordable.min(max_lots))
+}
+
+// The lots at which the squared error of the etf is smallest regardless of the budget: it is smallest
+// between these lots and the next ones.
+fn unconstrained_lots(budget: i64, etf: &EtfItem) -> i64 {
+    if etf.price <= 0 {
+        return 0;
+    }
+    ((etf.target - etf.cumulative) / (etf.price * etf.limits.lot_size())).clamp(0, max_lots(budget, etf))
+}
+
+// Minimizes the same total squared error as the knapsack, but with one integer variable per etf that
+// counts its lots. The squared error of an etf is convex in its quantity, so on integer quantities it
+// equals the maximum of the secants between consecutive quantities, which keeps the model linear. A
+// fixed fee or a minimum order goes through a binary variable per etf that has to be set for any
+// quantity to be bought, and forces the minimum when it is.
+//
+// Rather than one secant per affordable quantity, the model starts with the secants at nothing and at
+// the unconstrained optimum and gets the secant at every quantity of a solution whose error it
+// underestimates, until there is none. Every model is a relaxation of the one with all secants, so the
+// last solution is optimal, and the model only grows around the quantities the solver considers.
+pub fn solve_milp(budget: i64, etfs: &[EtfItem]) -> Result<Vec<i64>, ResolutionError> {
+    let mut secants = etfs.iter()
+        .map(|etf| BTreeSet::from([0, unconstrained_lots(budget, etf)]))
+        .collect::<Vec<_>>();
+    loop {
+        let (lots, errors) = solve_with_secants(budget, etfs, &secants)?;
+        let mut added = false;
+        for (((etf, &lots), error), secants) in etfs.iter().zip(&lots).zip(errors).zip(&mut secants) {
+            if error < squared_error(etf, lots * etf.limits.lot_size()) / scale(budget) - 1e-9 {
+                added |= secants.insert(lots);
+            }
+        }
+        if !added {
+            return Ok(etfs.iter().zip(lots).map(|(etf, lots)| lots * etf.limits.lot_size()).collect());
+        }
+    }
+}
+
+// Errors are in cents squared, scale them down so the solver tolerances stay meaningful.
+fn scale(budget: i64) -> f64 {
+    (budget.max(1) as f64).powi(2)
+}
+
+// The lots of each etf and the scaled error the model assigns to them, with the secants from each
+// number of lots in the set to the next.
+fn solve_with_secants(budget: i64, etfs: &[EtfItem], secants: &[BTreeSet<i64>]) -> Result<(Vec<i64>, Vec<f64>), ResolutionError> {
+    let scale = scale(budget);
+
+    let mut vars = variables!();
+    let lots = etfs.iter()
+        .map(|etf| vars.add(variable().integer().min(0).max(max_lots(budget, etf) as f64)))
+        .collect::<Vec<Variable>>();
+    let errors = etfs.iter()
+        .map(|_| vars.add(variable().min(0)))
+        .collect::<Vec<Variable>>();
+    let orders = etfs.iter()
+        .map(|etf| (etf.fee.fixed > 0 || etf.limits.min_lots(etf.price) > 1).then(|| vars.add(variable().binary())))
+        .collect::<Vec<Option<Variable>>>();
+
+    let total_error: Expression = errors.iter().sum();
+    let mut problem = vars.minimise(total_error).using(default_solver);
+
+    let total_cost: Expression = etfs.iter().zip(&lots).zip(&orders).map(|((etf, &lots), order)| {
+        let fixed_fee = order.map_or(Expression::from(0.0), |order| etf.fee.fixed as f64 * order);
+        lot_cost(etf) as f64 * lots + fixed_fee
+    }).sum();
+    problem = problem.with(constraint!(total_cost <= budget as f64));
+    for ((etf, &lots), order) in etfs.iter().zip(&lots).zip(&orders) {
+        if let Some(order) = order {
+            problem = problem.with(constraint!(lots <= max_lots(budget, etf) as f64 * *order));
+            problem = problem.with(constraint!(lots >= etf.limits.min_lots(etf.price) as f64 * *order));
+        }
+    }
+
+    for (((etf, &lots), &error), secants) in etfs.iter().zip(&lots).zip(&errors).zip(secants) {
+        let lot_size = etf.limits.lot_size();
+        for &k in secants {
+            let error_at_k = squared_error(etf, k * lot_size) / scale;
+            let slope = squared_error(etf, (k + 1) * lot_size) / scale - error_at_k;
+            problem = problem.with(constraint!(error >= error_at_k + slope * (lots - k as f64)));
+        }
+    }
+
+    let solution = problem.solve()?;
+    Ok((
+        lots.iter().map(|&lots| solution.value(lots).round() as i64).collect(),
+        errors.iter().map(|&error| solution.value(error)).collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calc_total_fees, calc_total_price, solve_etf_problem_with, Fee, OrderLimits, Solver};
+    use proptest::prelude::*;
+
+    fn calc_total_error(etfs: &[EtfItem], buy_quantities: &[i64]) -> i64 {
+        etfs.iter().zip(buy_quantities).map(|(etf, &quantity)| squared_error(etf, quantity) as i64).sum()
+    }
+
+    #[test]
+    fn test_solve_milp_three() {
+        let etfs = vec![
+            EtfItem::new(0, 200, 300),
+            EtfItem::new(0, 200, 200),
+            EtfItem::new(0, 200, 250),
+        ];
+        let quantities = solve_milp(600, &etfs).unwrap();
+        assert_eq!(calc_total_error(&etfs, &quantities), 42500);
+    }
+
+    #[test]
+    fn test_solve_milp_small_budget() {
+        let etfs = vec![
+            EtfItem::new(600, 800, 300),
+            EtfItem::new(600, 800, 200),
+        ];
+        assert_eq!(solve_milp(150, &etfs).unwrap(), vec![0, 0]);
+    }
+
+    #[test]
+    fn test_solve_milp_large_budget() {
+        // One secant per affordable quantity would be thousands of constraints.
+        let etfs = vec![
+            EtfItem::new(0, 600_000, 300),
+            EtfItem::new(100_000, 400_000, 100),
+        ];
+        assert_eq!(solve_milp(900_000, &etfs).unwrap(), vec![2_000, 3_000]);
+    }
+
+    fn etf_item() -> impl Strategy<Value = EtfItem> {
+        (0..500i64, 0..1000i64, 1..200i64).prop_map(|(cumulative, target, price)| EtfItem::new(cumulative, target, price))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_knap_sack_and_milp_agree(budget in 0..600i64, etfs in prop::collection::vec(etf_item(), 1..4)) {
+            let knap_sack = solve_etf_problem_with(Solver::KnapSack, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+            let milp = solve_etf_problem_with(Solver::Milp, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+
+            prop_assert!(calc_total_price(&etfs, &milp) <= budget);
+            prop_assert_eq!(calc_total_error(&etfs, &knap_sack), calc_total_error(&etfs, &milp));
+        }
+
+        #[test]
+        fn test_knap_sack_and_milp_agree_with_fees(
+            budget in 0..600i64,
+            etfs in prop::collection::vec((etf_item(), 0..200i64, 0..300i64), 1..4),
+        ) {
+            let etfs = etfs.into_iter().map(|(etf, fixed, basis_points)| etf.with_fee(Fee::new(fixed, basis_points))).collect::<Vec<_>>();
+            let knap_sack = solve_etf_problem_with(Solver::KnapSack, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+            let milp = solve_etf_problem_with(Solver::Milp, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+
+            prop_assert!(calc_total_price(&etfs, &milp) + calc_total_fees(&etfs, &milp) <= budget);
+            prop_assert_eq!(calc_total_error(&etfs, &knap_sack), calc_total_error(&etfs, &milp));
+        }
+
+        #[test]
+        fn test_knap_sack_and_milp_agree_with_limits(
+            budget in 0..600i64,
+            etfs in prop::collection::vec((etf_item(), 0..4i64, prop::option::of(0..8i64), 1..4i64, 0..300i64), 1..4),
+        ) {
+            let etfs = etfs.into_iter()
+                .map(|(etf, min_quantity, max_quantity, lot_size, min_amount)| etf.with_limits(OrderLimits::new(min_quantity, max_quantity, lot_size, min_amount)))
+                .collect::<Vec<_>>();
+            let knap_sack = solve_etf_problem_with(Solver::KnapSack, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+            let milp = solve_etf_problem_with(Solver::Milp, budget, etfs.clone()).unwrap().into_iter().map(|(_, q)| q).collect::<Vec<_>>();
+
+            prop_assert!(etfs.iter().zip(&milp).all(|(etf, &quantity)| etf.limits.allows(quantity, etf.price)));
+            prop_assert!(calc_total_price(&etfs, &milp) <= budget);
+            prop_assert_eq!(calc_total_error(&etfs, &knap_sack), calc_total_error(&etfs, &milp));
+        }
+    }
+}
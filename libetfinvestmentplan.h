@@ -1,24 +1,81 @@
+/*
+ * libetfinvestmentplan 0.2.0
+ *
+ * Breaks the ABI of 0.1.0: every function is prefixed with etfplan_, so that init and shutdown do not
+ * clash with libc, and several structs gained fields. Rebuild the host against this header:
+ *   search_etf_info     -> etfplan_search_etf_info
+ *   get_price_of        -> etfplan_get_price_of
+ *   suggest_investments -> etfplan_suggest_investments
+ *   persist_settings    -> etfplan_persist_settings, which returns a CErrorCode
+ *   get_settings        -> etfplan_get_settings
+ */
+
 #include <stdarg.h>
 #include <stdbool.h>
 #include <stdint.h>
 #include <stdlib.h>
 
+typedef enum CErrorCode {
+  CErrorCode_Ok = 0,
+  CErrorCode_NotInitialized = 1,
+  CErrorCode_InvalidArgument = 2,
+  CErrorCode_Sqlite = 3,
+  CErrorCode_YahooNetwork = 4,
+  CErrorCode_Parse = 5,
+  CErrorCode_AmbiguousIsin = 6,
+  CErrorCode_EtfNotFound = 7,
+  CErrorCode_NoBudget = 8,
+  CErrorCode_Panic = 9,
+} CErrorCode;
+
+typedef enum CInvestmentAction {
+  CInvestmentAction_Buy = 0,
+  CInvestmentAction_Sell = 1,
+} CInvestmentAction;
+
+typedef enum CValuation {
+  CValuation_CostBasis = 0,
+  CValuation_MarketValue = 1,
+} CValuation;
+
+typedef enum CInterval {
+  CInterval_Day = 0,
+  CInterval_Week = 1,
+  CInterval_Month = 2,
+} CInterval;
+
+typedef enum CPlanFormat {
+  CPlanFormat_Json = 0,
+  CPlanFormat_Toml = 1,
+} CPlanFormat;
+
 typedef struct CEtfInfo {
   const char *id;
   const char *name;
   const char *isin;
 } CEtfInfo;
 
+typedef struct CInitOptions {
+  bool create_if_missing;
+  uint32_t busy_timeout_ms;
+} CInitOptions;
+
 typedef struct CInvestment {
   const char *etf_id;
   const char *name;
   int64_t quantity;
   int64_t price;
+  int64_t price_fetched_at;
+  bool price_is_stale;
+  int64_t fee;
+  uint32_t quantity_decimals;
+  int32_t action;
 } CInvestment;
 
 typedef struct CInvestments {
   const struct CInvestment *investments;
   uintptr_t length;
+  int64_t total_fees;
 } CInvestments;
 
 typedef struct CEtfSetting {
@@ -27,6 +84,13 @@ typedef struct CEtfSetting {
   const char *name;
   double ideal_proportion;
   int64_t cumulative;
+  int64_t fee_fixed;
+  int64_t fee_basis_points;
+  int64_t min_quantity;
+  bool has_max_quantity;
+  int64_t max_quantity;
+  int64_t lot_size;
+  int64_t min_order_amount;
 } CEtfSetting;
 
 typedef struct CSettings {
@@ -35,12 +99,116 @@ typedef struct CSettings {
   uintptr_t num_etf_settings;
 } CSettings;
 
-const struct CEtfInfo *search_etf_info(const char *etf_isin_ptr);
+typedef struct CSuggestion {
+  struct CInvestment investment;
+  double current_proportion;
+  double ideal_proportion;
+  double post_purchase_proportion;
+  int64_t target;
+  double residual_error;
+} CSuggestion;
+
+typedef struct CSuggestions {
+  const struct CSuggestion *suggestions;
+  uintptr_t length;
+  int64_t total_fees;
+  int64_t left_over;
+} CSuggestions;
+
+typedef struct CRebalancing {
+  bool enabled;
+  int64_t max_turnover_basis_points;
+  int64_t sell_fee_fixed;
+  int64_t sell_fee_basis_points;
+} CRebalancing;
+
+typedef struct CPriceBar {
+  int64_t timestamp;
+  double open;
+  double high;
+  double low;
+  double close;
+  double adj_close;
+  int64_t volume;
+} CPriceBar;
+
+typedef struct CPriceBars {
+  const struct CPriceBar *bars;
+  uintptr_t length;
+} CPriceBars;
+
+enum CErrorCode etfplan_init(const char *db_path_ptr, const struct CInitOptions *options);
+
+enum CErrorCode etfplan_shutdown(void);
+
+enum CErrorCode etfplan_last_error_code(void);
+
+/**
+ * The message is owned by the library and stays valid until the next call into the library on the same thread.
+ */
+const char *etfplan_last_error_message(void);
+
+enum CErrorCode etfplan_use_price_fixtures(const char *fixtures_path_ptr);
+
+enum CErrorCode etfplan_set_price_cache_policy(int64_t refresh_after_seconds, int64_t max_age_seconds);
+
+enum CErrorCode etfplan_use_yahoo_prices(void);
+
+const struct CEtfInfo *etfplan_search_etf_info(const char *etf_isin_ptr);
+
+double etfplan_get_price_of(const char *etf_id_ptr);
+
+struct CInvestments etfplan_suggest_investments(void);
+
+struct CSuggestions etfplan_suggest_investments_explained(void);
+
+/**
+ * Fails with InvalidArgument, changing nothing, when an etf with purchases is given another cumulative amount than its ledger adds up to.
+ */
+enum CErrorCode etfplan_persist_settings(const struct CSettings *settings);
+
+enum CErrorCode etfplan_set_budget_currency(const char *currency_ptr);
+
+const char *etfplan_get_budget_currency(void);
+
+enum CErrorCode etfplan_set_fractional_shares(int32_t decimals);
+
+int32_t etfplan_get_fractional_shares(void);
+
+enum CErrorCode etfplan_set_valuation(int32_t valuation);
+
+enum CValuation etfplan_get_valuation(void);
+
+enum CErrorCode etfplan_set_rebalancing(struct CRebalancing rebalancing);
+
+struct CRebalancing etfplan_get_rebalancing(void);
+
+enum CErrorCode etfplan_confirm_investments(struct CInvestments investments);
+
+enum CErrorCode etfplan_fetch_price_history(const char *ticker_ptr,
+                                            int64_t start,
+                                            int64_t end,
+                                            int32_t interval);
+
+struct CPriceBars etfplan_get_price_history(const char *ticker_ptr,
+                                            int64_t start,
+                                            int64_t end,
+                                            int32_t interval);
+
+const char *etfplan_export_plan(int32_t format);
+
+enum CErrorCode etfplan_import_plan(const char *document_ptr, int32_t format);
+
+const struct CSettings *etfplan_get_settings(void);
+
+void etfplan_free_string(const char *string);
+
+void etfplan_free_etf_info(const struct CEtfInfo *etf_info);
 
-double get_price_of(const char *etf_id_ptr);
+void etfplan_free_investments(struct CInvestments investments);
 
-struct CInvestments suggest_investments(void);
+void etfplan_free_suggestions(struct CSuggestions suggestions);
 
-int64_t persist_settings(const struct CSettings *settings);
+void etfplan_free_price_bars(struct CPriceBars bars);
 
-const struct CSettings *get_settings(void);
+void etfplan_free_settings(const struct CSettings *settings);
@@ -0,0 +1,44 @@
+// Serves the plan over HTTP for the web dashboard, see the server module for the routes.
+
+use std::net::SocketAddr;
+use std::process::ExitCode;
+use std::sync::Arc;
+use clap::Parser;
+use database::DatabaseOptions;
+use etfinvestmentplan::{open_database, read_price_fixtures, server, set_price_provider, Error};
+
+#[derive(Debug, Parser)]
+#[command(name = "etfplan-server", about = "Serves the etf investment plan as a JSON API, described at /openapi.json.")]
+struct Cli {
+    /// The database file, which has to exist
+    #[arg(long, default_value = "db")]
+    db: String,
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Take prices from a fixtures file instead of Yahoo Finance
+    #[arg(long)]
+    fixtures: Option<String>,
+}
+
+fn run(cli: Cli) -> Result<(), Error> {
+    if let Some(fixtures) = &cli.fixtures {
+        set_price_provider(Arc::new(read_price_fixtures(fixtures)?));
+    }
+    open_database(&cli.db, DatabaseOptions::new(false, 5_000))?;
+
+    let runtime = tokio::runtime::Runtime::new().map_err(|e| Error::Panic(e.to_string()))?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(cli.listen).await?;
+        axum::serve(listener, server::router()).await
+    }).map_err(|e| Error::InvalidArgument(format!("could not serve on {}: {e}", cli.listen)))
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,580 @@
+// Command line access to the same plan the C library works on, for servers and scripts.
+
+use std::process::ExitCode;
+use std::sync::Arc;
+use chrono::{NaiveDate, NaiveTime};
+use clap::{Parser, Subcommand, ValueEnum};
+use database::{
+    parse_transactions, ColumnMapping, DatabaseOptions, EtfData, ImportedTransaction, PlanFormat, PriceBarData, PurchaseData, SCHEMA_VERSION,
+};
+use etfinvestmentplan::{
+    fetch_history, find_etf, get_settings_from_db, open_database, read_price_fixtures, record_investments, set_price_provider, suggest, with_db,
+    Error, Plan,
+};
+use investment_planner::{left_over_cash, total_fees, Action};
+use serde::Serialize;
+use yahoo_finance_info::Interval;
+
+#[derive(Debug, Parser)]
+#[command(name = "etfplan", about = "Plans etf investments towards ideal proportions. Amounts are in cents.")]
+struct Cli {
+    /// The database file
+    #[arg(long, global = true, default_value = "db")]
+    db: String,
+    /// Print JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+    /// Take prices from a fixtures file instead of Yahoo Finance
+    #[arg(long, global = true)]
+    fixtures: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create the database, or upgrade an existing one
+    Init {
+        /// The three letter currency of the budget
+        #[arg(long)]
+        currency: Option<String>,
+    },
+    /// Manage the etfs of the plan
+    #[command(subcommand)]
+    Etf(EtfCommand),
+    /// Manage the monthly budget
+    #[command(subcommand)]
+    Budget(BudgetCommand),
+    /// Suggest what to invest the budget in
+    Suggest,
+    /// Record the suggested investments as done today
+    Confirm,
+    /// Show the stored price history of a ticker
+    History {
+        ticker: String,
+        #[arg(long)]
+        from: NaiveDate,
+        #[arg(long)]
+        to: NaiveDate,
+        #[arg(long, value_enum, default_value_t = IntervalArg::Day)]
+        interval: IntervalArg,
+        /// Fetch the history from the price provider first
+        #[arg(long)]
+        fetch: bool,
+    },
+    /// Show the settings and the purchase ledger, or with --json or --toml write a backup for restore
+    Export {
+        #[arg(long, conflicts_with = "json")]
+        toml: bool,
+    },
+    /// Replace the plan with a backup written by export, as TOML when the file ends in .toml and JSON otherwise
+    Restore { file: String },
+    /// Record the purchases in a broker's transaction export, matching them to the etfs by isin
+    Import {
+        file: String,
+        #[arg(long, value_enum, default_value_t = Preset::Generic)]
+        preset: Preset,
+        /// Only show what would be recorded
+        #[arg(long)]
+        dry_run: bool,
+        #[command(flatten)]
+        columns: ColumnArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Preset {
+    /// Columns date, isin, quantity, price and fees, with dates as YYYY-MM-DD
+    Generic,
+    /// The Transactions.csv export of DEGIRO in English
+    Degiro,
+}
+
+// Changes to the columns of the preset.
+#[derive(Debug, clap::Args)]
+struct ColumnArgs {
+    #[arg(long)]
+    date_column: Option<String>,
+    /// The format of the dates, e.g. %d-%m-%Y
+    #[arg(long)]
+    date_format: Option<String>,
+    #[arg(long)]
+    isin_column: Option<String>,
+    #[arg(long)]
+    quantity_column: Option<String>,
+    #[arg(long, conflicts_with = "value_column")]
+    price_column: Option<String>,
+    /// The value of the whole transaction in the budget currency, divided by the quantity for the price
+    #[arg(long)]
+    value_column: Option<String>,
+    #[arg(long, conflicts_with = "no_fees")]
+    fees_column: Option<String>,
+    /// The export has no fees
+    #[arg(long)]
+    no_fees: bool,
+    #[arg(long)]
+    delimiter: Option<char>,
+    /// Numbers are written as 1.234,5
+    #[arg(long)]
+    decimal_comma: bool,
+}
+impl ColumnArgs {
+    fn mapping(self, preset: Preset) -> ColumnMapping {
+        let mapping = match preset {
+            Preset::Generic => ColumnMapping::default(),
+            Preset::Degiro => ColumnMapping::degiro(),
+        };
+        let mapping = ColumnMapping {
+            date: self.date_column.unwrap_or(mapping.date),
+            date_format: self.date_format.unwrap_or(mapping.date_format),
+            isin: self.isin_column.unwrap_or(mapping.isin),
+            quantity: self.quantity_column.unwrap_or(mapping.quantity),
+            fees: if self.no_fees { None } else { self.fees_column.or(mapping.fees) },
+            delimiter: self.delimiter.unwrap_or(mapping.delimiter),
+            ..mapping
+        };
+        let mapping = match (self.price_column, self.value_column) {
+            (Some(price), _) => mapping.with_price(price),
+            (_, Some(value)) => mapping.with_total_price(value),
+            _ => mapping,
+        };
+        if self.decimal_comma { mapping.with_decimal_comma(true) } else { mapping }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum EtfCommand {
+    /// Add the etf with this isin
+    Add {
+        isin: String,
+        #[arg(long)]
+        proportion: f64,
+        /// What is already invested in it
+        #[arg(long, default_value_t = 0)]
+        cumulative: i64,
+    },
+    /// Remove the etf with this ticker
+    Remove { id: String },
+    /// List the etfs
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum BudgetCommand {
+    /// Set the budget to invest each time
+    Set { amount: i64 },
+    /// Show the budget and its currency
+    Show,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum IntervalArg {
+    Day,
+    Week,
+    Month,
+}
+impl From<IntervalArg> for Interval {
+    fn from(interval: IntervalArg) -> Self {
+        match interval {
+            IntervalArg::Day => Interval::Day,
+            IntervalArg::Week => Interval::Week,
+            IntervalArg::Month => Interval::Month,
+        }
+    }
+}
+
+// A row of output, printed as a line of a table or as a JSON object.
+trait Row: Serialize {
+    const HEADERS: &'static [&'static str];
+    fn cells(&self) -> Vec<String>;
+}
+
+#[derive(Serialize)]
+struct EtfRow {
+    id: String,
+    isin: String,
+    name: String,
+    proportion: f64,
+    cumulative: i64,
+}
+impl From<EtfData> for EtfRow {
+    fn from(etf: EtfData) -> Self {
+        EtfRow { id: etf.id, isin: etf.isin, name: etf.name, proportion: etf.proportion, cumulative: etf.cumulative }
+    }
+}
+impl Row for EtfRow {
+    const HEADERS: &'static [&'static str] = &["id", "isin", "name", "proportion", "cumulative"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.id.clone(), self.isin.clone(), self.name.clone(), self.proportion.to_string(), self.cumulative.to_string()]
+    }
+}
+
+#[derive(Serialize)]
+struct BudgetRow {
+    budget: i64,
+    currency: String,
+}
+impl Row for BudgetRow {
+    const HEADERS: &'static [&'static str] = &["budget", "currency"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.budget.to_string(), self.currency.clone()]
+    }
+}
+
+#[derive(Serialize)]
+struct InvestmentRow {
+    etf_id: String,
+    action: &'static str,
+    quantity: f64,
+    price: i64,
+    fee: i64,
+    price_is_stale: bool,
+}
+impl Row for InvestmentRow {
+    const HEADERS: &'static [&'static str] = &["etf", "action", "quantity", "price", "fee", "stale price"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.etf_id.clone(), self.action.to_string(), self.quantity.to_string(), self.price.to_string(), self.fee.to_string(),
+            self.price_is_stale.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct PriceBarRow {
+    date: String,
+    open: f64,
+    high: f64,
+    low: f64,
+    close: f64,
+    adj_close: f64,
+    volume: i64,
+}
+impl From<PriceBarData> for PriceBarRow {
+    fn from(bar: PriceBarData) -> Self {
+        let date = chrono::DateTime::from_timestamp(bar.timestamp, 0).unwrap_or_default().date_naive().to_string();
+        PriceBarRow { date, open: bar.open, high: bar.high, low: bar.low, close: bar.close, adj_close: bar.adj_close, volume: bar.volume }
+    }
+}
+impl Row for PriceBarRow {
+    const HEADERS: &'static [&'static str] = &["date", "open", "high", "low", "close", "adj close", "volume"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.date.clone(), self.open.to_string(), self.high.to_string(), self.low.to_string(), self.close.to_string(),
+            self.adj_close.to_string(), self.volume.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct PurchaseRow {
+    id: i64,
+    etf_id: String,
+    quantity: f64,
+    unit_price: i64,
+    fees: i64,
+    date: String,
+}
+impl From<PurchaseData> for PurchaseRow {
+    fn from(purchase: PurchaseData) -> Self {
+        PurchaseRow {
+            quantity: purchase.units(), id: purchase.id, etf_id: purchase.etf_id, unit_price: purchase.unit_price, fees: purchase.fees,
+            date: purchase.date,
+        }
+    }
+}
+impl Row for PurchaseRow {
+    const HEADERS: &'static [&'static str] = &["id", "etf", "quantity", "unit price", "fees", "date"];
+    fn cells(&self) -> Vec<String> {
+        vec![
+            self.id.to_string(), self.etf_id.clone(), self.quantity.to_string(), self.unit_price.to_string(), self.fees.to_string(),
+            self.date.clone(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct SkippedRow {
+    line: usize,
+    isin: String,
+    date: String,
+    reason: &'static str,
+}
+impl SkippedRow {
+    fn new(transaction: ImportedTransaction, reason: &'static str) -> Self {
+        SkippedRow { line: transaction.line, isin: transaction.isin, date: transaction.date, reason }
+    }
+}
+impl Row for SkippedRow {
+    const HEADERS: &'static [&'static str] = &["line", "isin", "date", "skipped because"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.line.to_string(), self.isin.clone(), self.date.clone(), self.reason.to_string()]
+    }
+}
+
+#[derive(Serialize)]
+struct CumulativeRow {
+    etf_id: String,
+    before: i64,
+    after: i64,
+}
+impl Row for CumulativeRow {
+    const HEADERS: &'static [&'static str] = &["etf", "cumulative before", "after"];
+    fn cells(&self) -> Vec<String> {
+        vec![self.etf_id.clone(), self.before.to_string(), self.after.to_string()]
+    }
+}
+
+fn table<T: Row>(rows: &[T]) -> String {
+    let cells = rows.iter().map(Row::cells).collect::<Vec<_>>();
+    let widths = T::HEADERS.iter().enumerate()
+        .map(|(i, header)| cells.iter().map(|row| row[i].chars().count()).fold(header.len(), usize::max))
+        .collect::<Vec<_>>();
+    let line = |row: Vec<String>| row.iter().zip(&widths)
+        .map(|(cell, width)| format!("{cell:<width$}"))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_string();
+
+    let mut lines = vec![line(T::HEADERS.iter().map(|header| header.to_string()).collect())];
+    lines.extend(cells.into_iter().map(line));
+    lines.join("\n")
+}
+
+fn to_json(value: &impl Serialize) -> Result<String, Error> {
+    serde_json::to_string_pretty(value).map_err(|e| Error::Parse(format!("the output as JSON: {e}")))
+}
+
+fn rows<T: Row>(json: bool, rows: &[T]) -> Result<String, Error> {
+    if json { to_json(&rows) } else { Ok(table(rows)) }
+}
+
+fn day_start(date: NaiveDate) -> i64 {
+    date.and_time(NaiveTime::MIN).and_utc().timestamp()
+}
+
+fn plan_output(json: bool, plan: Plan) -> Result<String, Error> {
+    let investments = plan.investments.iter().map(|(investment, _)| investment.clone()).collect::<Vec<_>>();
+    let total_fees = total_fees(&investments);
+    let left_over = left_over_cash(plan.settings.budget, &investments, &plan.prices);
+    let rows = plan.investments.into_iter().map(|(investment, price)| InvestmentRow {
+        etf_id: investment.etf_id.clone(),
+        action: match investment.action {
+            Action::Buy => "buy",
+            Action::Sell => "sell",
+        },
+        quantity: investment.units(),
+        price: investment.price,
+        fee: investment.fee,
+        price_is_stale: price.is_stale,
+    }).collect::<Vec<_>>();
+
+    if json {
+        #[derive(Serialize)]
+        struct Suggestion {
+            investments: Vec<InvestmentRow>,
+            total_fees: i64,
+            left_over: i64,
+        }
+        return to_json(&Suggestion { investments: rows, total_fees, left_over });
+    }
+    Ok(format!("{}\n\ntotal fees: {total_fees}\nleft over: {left_over}", table(&rows)))
+}
+
+fn run(cli: Cli) -> Result<String, Error> {
+    if let Some(fixtures) = &cli.fixtures {
+        set_price_provider(Arc::new(read_price_fixtures(fixtures)?));
+    }
+    let create_if_missing = matches!(cli.command, Command::Init { .. });
+    open_database(&cli.db, DatabaseOptions::new(create_if_missing, 5_000))?;
+
+    match cli.command {
+        Command::Init { currency } => {
+            if let Some(currency) = currency {
+                if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(Error::InvalidArgument(format!("{currency} is not a three letter currency code")));
+                }
+                with_db(|db| Ok(db.set_currency(&currency.to_ascii_uppercase())?))?;
+            }
+            Ok(format!("{} is at schema version {SCHEMA_VERSION}", cli.db))
+        }
+        Command::Etf(EtfCommand::Add { isin, proportion, cumulative }) => {
+            if proportion.is_nan() || proportion < 0.0 || cumulative < 0 {
+                return Err(Error::InvalidArgument("the proportion and cumulative amount must not be negative".to_string()));
+            }
+            let etf = find_etf(&isin)?;
+            with_db(|db| {
+                if db.get_etf(&etf.ticker)?.is_some() {
+                    return Err(Error::InvalidArgument(format!("{} is already in the plan", etf.ticker)));
+                }
+                Ok(db.add_etf(EtfData::new(etf.ticker.clone(), etf.isin.clone(), etf.name.clone(), proportion, cumulative))?)
+            })?;
+            rows(cli.json, &[EtfRow { id: etf.ticker, isin: etf.isin, name: etf.name, proportion, cumulative }])
+        }
+        Command::Etf(EtfCommand::Remove { id }) => {
+            let etf = with_db(|db| {
+                let etf = db.get_etf(&id)?.ok_or_else(|| Error::InvalidArgument(format!("{id} is not in the plan")))?;
+                db.remove_etf(id.clone())?;
+                Ok(etf)
+            })?;
+            rows(cli.json, &[EtfRow::from(etf)])
+        }
+        Command::Etf(EtfCommand::List) => {
+            let etfs = with_db(|db| Ok(db.get_all_etfs()?.collect::<Result<Vec<_>, _>>()?))?;
+            rows(cli.json, &etfs.into_iter().map(EtfRow::from).collect::<Vec<_>>())
+        }
+        Command::Budget(command) => {
+            if let BudgetCommand::Set { amount } = command {
+                if amount < 0 {
+                    return Err(Error::InvalidArgument("the budget must not be negative".to_string()));
+                }
+                with_db(|db| Ok(db.set_budget(amount)?))?;
+            }
+            let budget = with_db(|db| Ok(BudgetRow { budget: db.get_budget()?.unwrap_or(0), currency: db.get_currency()?.unwrap_or_default() }))?;
+            rows(cli.json, &[budget])
+        }
+        Command::Suggest => plan_output(cli.json, suggest()?),
+        Command::Confirm => {
+            let plan = suggest()?;
+            record_investments(&plan.investments.iter().map(|(investment, _)| investment.clone()).collect::<Vec<_>>())?;
+            plan_output(cli.json, plan)
+        }
+        Command::History { ticker, from, to, interval, fetch } => {
+            let (start, end) = (day_start(from), day_start(to.succ_opt().unwrap_or(to)) - 1);
+            if fetch {
+                fetch_history(ticker.clone(), start, end, interval.into())?;
+            }
+            let interval = Interval::from(interval);
+            let bars = with_db(|db| Ok(db.get_price_bars(&ticker, interval.as_str(), start, end)?))?;
+            rows(cli.json, &bars.into_iter().map(PriceBarRow::from).collect::<Vec<_>>())
+        }
+        Command::Export { toml } => {
+            if cli.json || toml {
+                let format = if toml { PlanFormat::Toml } else { PlanFormat::Json };
+                return with_db(|db| Ok(db.export_plan(format)?));
+            }
+            let settings = get_settings_from_db()?;
+            let etfs = with_db(|db| Ok(db.get_all_etfs()?.collect::<Result<Vec<_>, _>>()?))?;
+            let purchases = with_db(|db| Ok(db.list_purchases()?))?.into_iter().map(PurchaseRow::from).collect::<Vec<_>>();
+            let currency = with_db(|db| Ok(db.get_currency()?))?.unwrap_or_default();
+            let etfs = etfs.into_iter().map(EtfRow::from).collect::<Vec<_>>();
+            Ok(format!("budget: {} {currency}\n\n{}\n\n{}", settings.budget, table(&etfs), table(&purchases)))
+        }
+        Command::Restore { file } => {
+            let document = std::fs::read_to_string(&file).map_err(|e| Error::InvalidArgument(format!("could not read {file}: {e}")))?;
+            let format = if file.ends_with(".toml") { PlanFormat::Toml } else { PlanFormat::Json };
+            let plan = with_db(|db| Ok(db.import_plan(&document, format)?))?;
+            rows(cli.json, &plan.etfs.into_iter().map(EtfRow::from).collect::<Vec<_>>())
+        }
+        Command::Import { file, preset, dry_run, columns } => {
+            let csv = std::fs::read_to_string(&file).map_err(|e| Error::InvalidArgument(format!("could not read {file}: {e}")))?;
+            let transactions = parse_transactions(&csv, &columns.mapping(preset))?;
+            let diff = with_db(|db| Ok(db.import_transactions(&transactions, dry_run)?))?;
+
+            let added = diff.added.into_iter().map(PurchaseRow::from).collect::<Vec<_>>();
+            let skipped = diff.duplicates.into_iter().map(|transaction| SkippedRow::new(transaction, "already recorded"))
+                .chain(diff.unmatched.into_iter().map(|transaction| SkippedRow::new(transaction, "no etf has the isin")))
+                .collect::<Vec<_>>();
+            let cumulative = diff.cumulative_changes.into_iter()
+                .map(|(etf_id, before, after)| CumulativeRow { etf_id, before, after })
+                .collect::<Vec<_>>();
+            if cli.json {
+                #[derive(Serialize)]
+                struct Import {
+                    dry_run: bool,
+                    added: Vec<PurchaseRow>,
+                    skipped: Vec<SkippedRow>,
+                    cumulative: Vec<CumulativeRow>,
+                }
+                return to_json(&Import { dry_run, added, skipped, cumulative });
+            }
+            let title = if dry_run { "would record" } else { "recorded" };
+            Ok(format!("{title}:\n{}\n\n{}\n\n{}", table(&added), table(&skipped), table(&cumulative)))
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn etfplan(db: &str, args: &[&str]) -> Result<String, Error> {
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/yahoo-finance-info/fixtures/prices.json");
+        let cli = Cli::try_parse_from(["etfplan", "--db", db, "--fixtures", fixtures].iter().chain(args))
+            .map_err(|e| Error::InvalidArgument(e.to_string()))?;
+        run(cli)
+    }
+
+    #[test]
+    fn test_table_pads_columns() {
+        let rows = [BudgetRow { budget: 50_000, currency: "EUR".to_string() }];
+        assert_eq!(table(&rows), "budget  currency\n50000   EUR");
+    }
+
+    #[test]
+    fn test_plan_from_the_command_line() {
+        let path = std::env::temp_dir().join(format!("etfplan-{}.db", std::process::id()));
+        let db = path.to_str().unwrap();
+
+        assert!(matches!(etfplan(db, &["etf", "list"]), Err(Error::Sqlite(_))));
+        etfplan(db, &["init", "--currency", "eur"]).unwrap();
+        etfplan(db, &["etf", "add", "IE00B3ZW0K18", "--proportion", "0.5"]).unwrap();
+        etfplan(db, &["etf", "add", "IE00B3F81409", "--proportion", "0.5"]).unwrap();
+        etfplan(db, &["budget", "set", "50000"]).unwrap();
+
+        let etfs: serde_json::Value = serde_json::from_str(&etfplan(db, &["--json", "etf", "list"]).unwrap()).unwrap();
+        assert_eq!(etfs[0]["id"], "IUSE.L");
+        assert_eq!(etfs[1]["id"], "AGGG.L");
+
+        let suggestion: serde_json::Value = serde_json::from_str(&etfplan(db, &["--json", "suggest"]).unwrap()).unwrap();
+        assert_eq!(suggestion["investments"][0]["quantity"], 2.0);
+        assert_eq!(suggestion["investments"][1]["quantity"], 51.0);
+
+        etfplan(db, &["confirm"]).unwrap();
+        let export: serde_json::Value = serde_json::from_str(&etfplan(db, &["export", "--json"]).unwrap()).unwrap();
+        assert_eq!(export["currency"], "EUR");
+        assert_eq!(export["purchases"].as_array().unwrap().len(), 2);
+        let backup = path.with_extension("toml");
+        std::fs::write(&backup, etfplan(db, &["export", "--toml"]).unwrap()).unwrap();
+        etfplan(db, &["budget", "set", "1"]).unwrap();
+        assert_eq!(etfplan(db, &["restore", backup.to_str().unwrap()]).unwrap().lines().count(), 3);
+        assert!(etfplan(db, &["budget", "show"]).unwrap().contains("50000"));
+        std::fs::remove_file(&backup).unwrap();
+
+        let history = etfplan(db, &["history", "IUSE.L", "--from", "2025-01-02", "--to", "2025-01-03", "--fetch"]).unwrap();
+        assert_eq!(history.lines().count(), 3);
+
+        let csv = path.with_extension("csv");
+        std::fs::write(&csv, "date,isin,quantity,price,fees\n2025-01-02,IE00B3ZW0K18,1,110.02,1.00\n2025-01-02,IE00B4L5Y983,1,98.12,\n").unwrap();
+        let import = |dry_run: &str| {
+            let output = etfplan(db, &["--json", "import", csv.to_str().unwrap(), dry_run]).unwrap();
+            serde_json::from_str::<serde_json::Value>(&output).unwrap()
+        };
+        let dry_run = import("--dry-run");
+        assert_eq!(dry_run["added"].as_array().unwrap().len(), 1);
+        assert_eq!(dry_run["skipped"][0]["line"], 3);
+        assert_eq!(dry_run["cumulative"][0]["after"], dry_run["cumulative"][0]["before"].as_i64().unwrap() + 11_002);
+        import("--no-fees");
+        assert_eq!(import("--dry-run")["added"].as_array().unwrap().len(), 0);
+        std::fs::remove_file(&csv).unwrap();
+
+        let removed = etfplan(db, &["etf", "remove", "AGGG.L"]).unwrap();
+        assert!(removed.contains("AGGG.L"));
+        assert!(etfplan(db, &["etf", "remove", "AGGG.L"]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
+use std::fmt::{self, Display};
+use std::panic::{self, AssertUnwindSafe};
+use database::{ImportError, PlanError, SqliteError};
+use yahoo_finance_info::YahooError;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CErrorCode {
+    Ok = 0,
+    NotInitialized = 1,
+    InvalidArgument = 2,
+    Sqlite = 3,
+    YahooNetwork = 4,
+    Parse = 5,
+    AmbiguousIsin = 6,
+    EtfNotFound = 7,
+    NoBudget = 8,
+    Panic = 9,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    NotInitialized,
+    InvalidArgument(String),
+    Sqlite(SqliteError),
+    Yahoo(YahooError),
+    Parse(String),
+    AmbiguousIsin(String),
+    EtfNotFound(String),
+    NoBudget,
+    Panic(String),
+}
+
+impl Error {
+    pub fn code(&self) -> CErrorCode {
+        match self {
+            Error::NotInitialized => CErrorCode::NotInitialized,
+            Error::InvalidArgument(_) => CErrorCode::InvalidArgument,
+            Error::Sqlite(_) => CErrorCode::Sqlite,
+            Error::Yahoo(YahooError::FetchFailed(_) | YahooError::ConnectionFailed(_) | YahooError::BuilderFailed) => CErrorCode::YahooNetwork,
+            // Yahoo answers a ticker it does not know with no data rather than an error.
+            Error::Yahoo(YahooError::EmptyDataSet) => CErrorCode::EtfNotFound,
+            Error::Yahoo(_) | Error::Parse(_) => CErrorCode::Parse,
+            Error::AmbiguousIsin(_) => CErrorCode::AmbiguousIsin,
+            Error::EtfNotFound(_) => CErrorCode::EtfNotFound,
+            Error::NoBudget => CErrorCode::NoBudget,
+            Error::Panic(_) => CErrorCode::Panic,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotInitialized => write!(f, "the database is not initialized, call etfplan_init first"),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {message}"),
+            Error::Sqlite(e) => write!(f, "database error: {e}"),
+            Error::Yahoo(e) => write!(f, "yahoo finance error: {e}"),
+            Error::Parse(message) => write!(f, "could not parse {message}"),
+            Error::AmbiguousIsin(isin) => write!(f, "found more than one etf with isin {isin}"),
+            Error::EtfNotFound(isin) => write!(f, "could not find an etf with isin {isin}"),
+            Error::NoBudget => write!(f, "no budget is set"),
+            Error::Panic(message) => write!(f, "internal error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<SqliteError> for Error {
+    fn from(e: SqliteError) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+impl From<ImportError> for Error {
+    fn from(e: ImportError) -> Self {
+        match e {
+            ImportError::Sqlite(e) => Error::Sqlite(e),
+            ImportError::Parse { line, message } => Error::Parse(format!("line {line}: {message}")),
+        }
+    }
+}
+
+impl From<PlanError> for Error {
+    fn from(e: PlanError) -> Self {
+        match e {
+            PlanError::Sqlite(e) => Error::Sqlite(e),
+            PlanError::Parse(message) => Error::Parse(format!("the plan: {message}")),
+            PlanError::Invalid(message) => Error::InvalidArgument(message),
+        }
+    }
+}
+
+impl From<YahooError> for Error {
+    fn from(e: YahooError) -> Self {
+        Error::Yahoo(e)
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(CErrorCode, CString)>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(error: &Error) {
+    let message = CString::new(error.to_string().replace('\0', "")).expect("0 bytes were removed from the message");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((error.code(), message)));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|last| *last.borrow_mut() = None);
+}
+
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(Error::Panic(message))
+    })
+}
+
+pub(crate) fn report<T>(result: Result<T, Error>) -> Option<T> {
+    match result {
+        Err(e) => {
+            set_last_error(&e);
+            None
+        }
+        Ok(t) => {
+            clear_last_error();
+            Some(t)
+        }
+    }
+}
+
+pub(crate) fn report_code(result: Result<(), Error>) -> CErrorCode {
+    match report(result) {
+        None => etfplan_last_error_code(),
+        Some(()) => CErrorCode::Ok,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_last_error_code() -> CErrorCode {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(CErrorCode::Ok, |(code, _)| *code))
+}
+
+/// The message is owned by the library and stays valid until the next call into the library on the same thread.
+#[no_mangle]
+pub extern "C" fn etfplan_last_error_message() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |(_, message)| message.as_ptr()))
+}
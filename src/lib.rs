@@ -1,38 +1,130 @@
+// Every exported function is called from C, where there is no `unsafe` to mark pointer arguments with.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
+mod error;
+mod prices;
+#[cfg(feature = "server")]
+pub mod server;
+
 use std::ffi::{c_char, CStr, CString};
-use std::fmt::Display;
-use std::mem;
-use std::sync::{LazyLock, Mutex};
-use database::{Database, EtfData, SqliteError};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError, RwLock};
+use database::{Database, DatabaseOptions, DividendData, EtfData, PlanFormat, PriceBarData, RebalancingData};
 use derive_new::new;
-use investment_planner::{EtfSetting, Investment, Settings};
+use investment_planner::{drift_report, left_over_cash, total_fees, Action, Allocation, EtfSetting, Fee, Investment, OrderLimits, Rebalancing, Settings, Valuation, MAX_DECIMALS};
 use tokio::runtime::Runtime;
-use yahoo_finance_info::YahooError;
-use futures::future;
+use yahoo_finance_info::{FixturePriceProvider, Interval, PriceProvider, YahooPriceProvider, ETF};
+use crate::error::{catch_panic, report, report_code};
+use crate::prices::{convert_prices, get_cached_prices, PriceCachePolicy};
+
+pub use crate::error::{etfplan_last_error_code, etfplan_last_error_message, CErrorCode, Error};
+pub use crate::prices::CachedPrice;
 
 static RT: LazyLock<Runtime> = LazyLock::new(|| Runtime::new().expect("Could not initialize tokio async runtime"));
-static DB: LazyLock<Mutex<Database>> = LazyLock::new(|| Mutex::new(Database::new("db").expect("Could not create database from 'db' file")));
+static DB: Mutex<Option<Database>> = Mutex::new(None);
+static PRICE_PROVIDER: RwLock<Option<Arc<dyn PriceProvider>>> = RwLock::new(None);
 
-fn c_char_ptr_to_string(c_ptr: *const c_char) -> String {
+const DEFAULT_CURRENCY: &str = "EUR";
+
+fn c_char_ptr_to_string(c_ptr: *const c_char) -> Result<String, Error> {
+    if c_ptr.is_null() {
+        return Err(Error::InvalidArgument("unexpected null string".to_string()));
+    }
     unsafe {
         CStr::from_ptr(c_ptr)
-    }.to_str().expect("could not parse C string as UTF-8").to_string()
+    }.to_str().map(str::to_string).map_err(|e| Error::Parse(format!("C string as UTF-8: {e}")))
 }
 
 fn string_to_c_char_ptr(xs: String) -> *const c_char {
     CString::new(xs).expect("unexpectedly found 0 byte in String").into_raw()
 }
 
+unsafe fn free_c_char_ptr(c_ptr: *const c_char) {
+    if !c_ptr.is_null() {
+        drop(CString::from_raw(c_ptr as *mut c_char));
+    }
+}
+
+fn vec_to_c_array<T>(xs: Vec<T>) -> (*const T, usize) {
+    let len = xs.len();
+    (Box::into_raw(xs.into_boxed_slice()) as *const T, len)
+}
+
+unsafe fn c_array_to_vec<T>(c_ptr: *const T, len: usize) -> Vec<T> {
+    if c_ptr.is_null() {
+        return vec![];
+    }
+    Box::from_raw(std::ptr::slice_from_raw_parts_mut(c_ptr as *mut T, len)).into_vec()
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, new)]
+pub struct CInitOptions {
+    pub create_if_missing: bool,
+    pub busy_timeout_ms: u32,
+}
+impl From<CInitOptions> for DatabaseOptions {
+    fn from(options: CInitOptions) -> Self {
+        DatabaseOptions::new(options.create_if_missing, options.busy_timeout_ms as usize)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CInvestmentAction {
+    Buy = 0,
+    Sell = 1,
+}
+impl From<Action> for CInvestmentAction {
+    fn from(action: Action) -> Self {
+        match action {
+            Action::Buy => CInvestmentAction::Buy,
+            Action::Sell => CInvestmentAction::Sell,
+        }
+    }
+}
+impl TryFrom<i32> for CInvestmentAction {
+    type Error = Error;
+    fn try_from(action: i32) -> Result<Self, Error> {
+        match action {
+            0 => Ok(CInvestmentAction::Buy),
+            1 => Ok(CInvestmentAction::Sell),
+            _ => Err(Error::InvalidArgument(format!("{action} is not a CInvestmentAction"))),
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(new)]
+#[allow(clippy::too_many_arguments)]
 pub struct CInvestment {
     pub etf_id: *const c_char,
     pub name: *const c_char,
     pub quantity: i64,
-    pub price: i64
+    pub price: i64,
+    // Unix time in seconds at which the price was fetched from the provider.
+    pub price_fetched_at: i64,
+    // Set when the provider could not be reached and an older cached price was used instead.
+    pub price_is_stale: bool,
+    // The broker's fee for the order, in cents on top of quantity times price.
+    pub fee: i64,
+    // The quantity is in units of 10^-quantity_decimals shares, so 0 means whole shares.
+    pub quantity_decimals: u32,
+    // A CInvestmentAction, as an integer since C can put any value in it. Sells are only suggested when
+    // rebalancing is on, and come after the buys.
+    pub action: i32,
 }
-impl From<Investment> for CInvestment {
-    fn from(investment: Investment) -> Self {
-        CInvestment::new(string_to_c_char_ptr(investment.etf_id), string_to_c_char_ptr(investment.name), investment.quantity, investment.price)
+impl From<(Investment, CachedPrice)> for CInvestment {
+    fn from((investment, price): (Investment, CachedPrice)) -> Self {
+        CInvestment::new(
+            string_to_c_char_ptr(investment.etf_id), string_to_c_char_ptr(investment.name), investment.quantity, investment.price,
+            price.fetched_at, price.is_stale, investment.fee, investment.decimals, CInvestmentAction::from(investment.action) as i32,
+        )
+    }
+}
+impl CInvestment {
+    unsafe fn free(self) {
+        free_c_char_ptr(self.etf_id);
+        free_c_char_ptr(self.name);
     }
 }
 
@@ -41,36 +133,65 @@ impl From<Investment> for CInvestment {
 pub struct CInvestments {
     pub investments: *const CInvestment,
     pub length: usize,
+    pub total_fees: i64,
 }
-impl From<Vec<Investment>> for CInvestments {
-    fn from(investments: Vec<Investment>) -> Self {
-        let mut c_investments = investments.into_iter().map(|i| CInvestment::from(i)).collect::<Vec<_>>();
-        c_investments.shrink_to_fit();
-        let len = c_investments.len();
-        let c_investments_ptr = c_investments.as_ptr();
-        mem::forget(c_investments);
+impl From<Vec<(Investment, CachedPrice)>> for CInvestments {
+    fn from(investments: Vec<(Investment, CachedPrice)>) -> Self {
+        let total_fees = investments.iter().map(|(investment, _)| investment.fee).sum();
+        let c_investments = investments.into_iter().map(CInvestment::from).collect::<Vec<_>>();
+        let (c_investments_ptr, len) = vec_to_c_array(c_investments);
 
-        CInvestments::new(c_investments_ptr, len)
+        CInvestments::new(c_investments_ptr, len, total_fees)
     }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy, new)]
+#[allow(clippy::too_many_arguments)]
 pub struct CEtfSetting {
     pub id: *const c_char,
     pub isin: *const c_char,
     pub name: *const c_char,
     pub ideal_proportion: f64,
-    pub cumulative: i64
+    pub cumulative: i64,
+    pub fee_fixed: i64,
+    pub fee_basis_points: i64,
+    pub min_quantity: i64,
+    // Without has_max_quantity there is no maximum, so a zeroed struct puts no limit on the quantity.
+    pub has_max_quantity: bool,
+    pub max_quantity: i64,
+    // 0 is taken as 1, i.e. no lots.
+    pub lot_size: i64,
+    pub min_order_amount: i64,
 }
 impl CEtfSetting {
-    fn etf_setting(&self) -> EtfSetting {
-        EtfSetting::new(c_char_ptr_to_string(self.id), c_char_ptr_to_string(self.isin), c_char_ptr_to_string(self.name), self.ideal_proportion, self.cumulative)
+    fn etf_setting(&self) -> Result<EtfSetting, Error> {
+        if self.fee_fixed < 0 || self.fee_basis_points < 0 {
+            return Err(Error::InvalidArgument("fees cannot be negative".to_string()));
+        }
+        if self.min_quantity < 0 || self.max_quantity < 0 || self.lot_size < 0 || self.min_order_amount < 0 {
+            return Err(Error::InvalidArgument("order limits cannot be negative".to_string()));
+        }
+        let max_quantity = self.has_max_quantity.then_some(self.max_quantity);
+        Ok(EtfSetting::new(c_char_ptr_to_string(self.id)?, c_char_ptr_to_string(self.isin)?, c_char_ptr_to_string(self.name)?, self.ideal_proportion, self.cumulative)
+            .with_fee(Fee::new(self.fee_fixed, self.fee_basis_points))
+            .with_limits(OrderLimits::new(self.min_quantity, max_quantity, self.lot_size.max(1), self.min_order_amount)))
+    }
+
+    unsafe fn free(self) {
+        free_c_char_ptr(self.id);
+        free_c_char_ptr(self.isin);
+        free_c_char_ptr(self.name);
     }
 }
 impl From<EtfSetting> for CEtfSetting {
     fn from(etf_setting: EtfSetting) -> Self {
-        CEtfSetting::new(string_to_c_char_ptr(etf_setting.id), string_to_c_char_ptr(etf_setting.isin), string_to_c_char_ptr(etf_setting.name), etf_setting.ideal_proportion, etf_setting.cumulative)
+        CEtfSetting::new(
+            string_to_c_char_ptr(etf_setting.id), string_to_c_char_ptr(etf_setting.isin), string_to_c_char_ptr(etf_setting.name), etf_setting.ideal_proportion, etf_setting.cumulative,
+            etf_setting.fee.fixed, etf_setting.fee.basis_points,
+            etf_setting.limits.min_quantity, etf_setting.limits.max_quantity.is_some(), etf_setting.limits.max_quantity.unwrap_or(0),
+            etf_setting.limits.lot_size, etf_setting.limits.min_amount,
+        )
     }
 }
 
@@ -83,32 +204,30 @@ pub struct CSettings {
 }
 
 impl CSettings {
-    fn settings(&self) -> Settings {
+    fn settings(&self) -> Result<Settings, Error> {
         let budget = self.budget;
+        if self.num_etf_settings > 0 && self.etf_settings.is_null() {
+            return Err(Error::InvalidArgument("etf_settings is null".to_string()));
+        }
 
         let mut etf_settings = vec![];
         for i in 0..self.num_etf_settings {
             let etf_setting = unsafe {
                 *self.etf_settings.add(i)
-            }.etf_setting();
+            }.etf_setting()?;
             etf_settings.push(etf_setting);
         }
         
-        Settings::new(budget, etf_settings)
+        Ok(Settings::new(budget, etf_settings))
     }
 }
 impl From<Settings> for CSettings {
     fn from(settings: Settings) -> Self {
-        let mut c_etf_settings = settings.etf_settings
+        let c_etf_settings = settings.etf_settings
             .into_iter()
-            .map(|etf| 
-                CEtfSetting::from(etf)
-            )
+            .map(CEtfSetting::from)
             .collect::<Vec<_>>();
-        c_etf_settings.shrink_to_fit();
-        let len = c_etf_settings.len();
-        let etf_settings_ptr = c_etf_settings.as_ptr();
-        mem::forget(c_etf_settings);
+        let (etf_settings_ptr, len) = vec_to_c_array(c_etf_settings);
 
         CSettings::new(settings.budget, etf_settings_ptr, len)
     }
@@ -121,150 +240,1260 @@ pub struct CEtfInfo {
     pub name: *const c_char,
     pub isin: *const c_char,
 }
+impl CEtfInfo {
+    unsafe fn free(self) {
+        free_c_char_ptr(self.id);
+        free_c_char_ptr(self.name);
+        free_c_char_ptr(self.isin);
+    }
+}
+
+#[repr(C)]
+#[derive(new)]
+pub struct CSuggestion {
+    pub investment: CInvestment,
+    // The etf's share of the holdings before and after the investments, and the share it should have.
+    pub current_proportion: f64,
+    pub ideal_proportion: f64,
+    pub post_purchase_proportion: f64,
+    // The amount in cents the etf was aimed at for this budget.
+    pub target: i64,
+    // How far from its target the etf ends up, squared, as minimized when choosing the quantities.
+    pub residual_error: f64,
+}
 
-fn check_result<T, E: Display, Ret, RetErr: Fn() -> Ret, RetOk: Fn(T) -> Ret>(result: Result<T, E>, ret_err: RetErr, ret_ok: RetOk) -> Ret {
-    match result {
-        Err(e) => {
-            eprintln!("{e}");
-            ret_err()
+#[repr(C)]
+#[derive(new)]
+pub struct CSuggestions {
+    pub suggestions: *const CSuggestion,
+    pub length: usize,
+    pub total_fees: i64,
+    // What is left of the budget after buying, selling and paying the fees.
+    pub left_over: i64,
+}
+impl From<Plan> for CSuggestions {
+    fn from(plan: Plan) -> Self {
+        let investments = plan.investments.iter().map(|(investment, _)| investment.clone()).collect::<Vec<_>>();
+        let drifts = drift_report(&plan.settings, &plan.prices, &investments);
+        let total_fees = total_fees(&investments);
+        let left_over = left_over_cash(plan.settings.budget, &investments, &plan.prices);
+
+        let suggestions = plan.investments.into_iter().map(|(investment, price)| {
+            let i = plan.settings.etf_settings.iter().position(|etf| etf.id == investment.etf_id).expect("investments are in configured etfs");
+            let drift = &drifts[i];
+            CSuggestion::new(
+                CInvestment::from((investment, price)),
+                drift.current_proportion, drift.ideal_proportion, drift.post_purchase_proportion, drift.target, drift.residual_error,
+            )
+        }).collect::<Vec<_>>();
+        let (suggestions_ptr, len) = vec_to_c_array(suggestions);
+
+        CSuggestions::new(suggestions_ptr, len, total_fees, left_over)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, new)]
+pub struct CRebalancing {
+    // Without enabled only buys are suggested, so a zeroed struct turns rebalancing off.
+    pub enabled: bool,
+    // The most that may be sold at once, in hundredths of a percent of the holdings.
+    pub max_turnover_basis_points: i64,
+    pub sell_fee_fixed: i64,
+    pub sell_fee_basis_points: i64,
+}
+impl CRebalancing {
+    fn rebalancing(&self) -> Result<Option<RebalancingData>, Error> {
+        if self.max_turnover_basis_points < 0 || self.sell_fee_fixed < 0 || self.sell_fee_basis_points < 0 {
+            return Err(Error::InvalidArgument("the turnover limit and sell fee must not be negative".to_string()));
+        }
+        Ok(self.enabled.then(|| RebalancingData::new(self.max_turnover_basis_points, self.sell_fee_fixed, self.sell_fee_basis_points)))
+    }
+}
+impl From<Option<RebalancingData>> for CRebalancing {
+    fn from(rebalancing: Option<RebalancingData>) -> Self {
+        match rebalancing {
+            Some(r) => CRebalancing::new(true, r.max_turnover_basis_points, r.sell_fee_fixed, r.sell_fee_basis_points),
+            None => CRebalancing::new(false, 0, 0, 0),
         }
-        Ok(t) => ret_ok(t)
     }
 }
 
-fn check_option<T, Ret, RetNone: Fn() -> Ret, RetSome: Fn(T) -> Ret>(option: Option<T>, ret_none: RetNone, ret_some: RetSome) -> Ret {
-    match option {
-        None => ret_none(),
-        Some(t) => ret_some(t)
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CValuation {
+    CostBasis = 0,
+    MarketValue = 1,
+}
+impl TryFrom<i32> for CValuation {
+    type Error = Error;
+    fn try_from(valuation: i32) -> Result<Self, Error> {
+        match valuation {
+            0 => Ok(CValuation::CostBasis),
+            1 => Ok(CValuation::MarketValue),
+            _ => Err(Error::InvalidArgument(format!("{valuation} is not a CValuation"))),
+        }
+    }
+}
+impl From<CValuation> for Valuation {
+    fn from(valuation: CValuation) -> Self {
+        match valuation {
+            CValuation::CostBasis => Valuation::CostBasis,
+            CValuation::MarketValue => Valuation::MarketValue,
+        }
     }
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CInterval {
+    Day = 0,
+    Week = 1,
+    Month = 2,
+}
+impl TryFrom<i32> for CInterval {
+    type Error = Error;
+    fn try_from(interval: i32) -> Result<Self, Error> {
+        match interval {
+            0 => Ok(CInterval::Day),
+            1 => Ok(CInterval::Week),
+            2 => Ok(CInterval::Month),
+            _ => Err(Error::InvalidArgument(format!("{interval} is not a CInterval"))),
+        }
+    }
+}
+impl From<CInterval> for Interval {
+    fn from(interval: CInterval) -> Self {
+        match interval {
+            CInterval::Day => Interval::Day,
+            CInterval::Week => Interval::Week,
+            CInterval::Month => Interval::Month,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CPlanFormat {
+    Json = 0,
+    Toml = 1,
+}
+impl TryFrom<i32> for CPlanFormat {
+    type Error = Error;
+    fn try_from(format: i32) -> Result<Self, Error> {
+        match format {
+            0 => Ok(CPlanFormat::Json),
+            1 => Ok(CPlanFormat::Toml),
+            _ => Err(Error::InvalidArgument(format!("{format} is not a CPlanFormat"))),
+        }
+    }
+}
+impl From<CPlanFormat> for PlanFormat {
+    fn from(format: CPlanFormat) -> Self {
+        match format {
+            CPlanFormat::Json => PlanFormat::Json,
+            CPlanFormat::Toml => PlanFormat::Toml,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(new)]
+pub struct CPriceBar {
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub adj_close: f64,
+    pub volume: i64,
+}
+impl From<PriceBarData> for CPriceBar {
+    fn from(bar: PriceBarData) -> Self {
+        CPriceBar::new(bar.timestamp, bar.open, bar.high, bar.low, bar.close, bar.adj_close, bar.volume)
+    }
+}
+
+#[repr(C)]
+#[derive(new)]
+pub struct CPriceBars {
+    pub bars: *const CPriceBar,
+    pub length: usize,
+}
+impl From<Vec<PriceBarData>> for CPriceBars {
+    fn from(bars: Vec<PriceBarData>) -> Self {
+        let (bars_ptr, len) = vec_to_c_array(bars.into_iter().map(CPriceBar::from).collect());
+        CPriceBars::new(bars_ptr, len)
+    }
+}
+
+// Multi-statement writes go through Database::transaction, whose savepoint is rolled back when a panic
+// unwinds through it, so a poisoned lock still guards a consistent database. Recover from poisoning
+// instead of failing every later call.
+fn lock_db() -> MutexGuard<'static, Option<Database>> {
+    DB.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn with_db<T>(f: impl FnOnce(&Database) -> Result<T, Error>) -> Result<T, Error> {
+    let db = lock_db();
+    let db = db.as_ref().ok_or(Error::NotInitialized)?;
+    f(db)
+}
+
+// Opens the database every other call goes through, replacing the one opened before.
+pub fn open_database(db_path: &str, options: DatabaseOptions) -> Result<(), Error> {
+    let db = Database::open(db_path, options)?;
+    *lock_db() = Some(db);
+    Ok(())
+}
+
+fn init_db(db_path_ptr: *const c_char, options: *const CInitOptions) -> Result<(), Error> {
+    let db_path = c_char_ptr_to_string(db_path_ptr)?;
+    let options = if options.is_null() {
+        DatabaseOptions::default()
+    } else {
+        DatabaseOptions::from(unsafe { *options })
+    };
+    open_database(&db_path, options)
+}
+
 #[no_mangle]
-pub extern "C" fn search_etf_info(etf_isin_ptr: *const c_char) -> *const CEtfInfo {
-    let etf_isin = c_char_ptr_to_string(etf_isin_ptr);
-
-    let result = RT.block_on(yahoo_finance_info::search_etf_isin(&etf_isin));
-    check_result(result, 
-        || std::ptr::null(), 
-        |xs| {
-            if xs.len() > 1 {
-                eprintln!("found more than 1 result while searching for {}. Returning null.", etf_isin);
-                return std::ptr::null();
-            }
-            let opt = xs.into_iter().next();
-            check_option(opt, 
-                || {
-                    eprintln!("could not find an etf with isin = {}", etf_isin);
-                    std::ptr::null()
-                }, 
-                |x| {
-                    let etf_info = CEtfInfo::new(string_to_c_char_ptr(x.ticker), string_to_c_char_ptr(x.name), string_to_c_char_ptr(x.isin));
-                    Box::into_raw(Box::new(etf_info))
-                })
-        })
+pub extern "C" fn etfplan_init(db_path_ptr: *const c_char, options: *const CInitOptions) -> CErrorCode {
+    report_code(catch_panic(|| init_db(db_path_ptr, options)))
 }
 
 #[no_mangle]
-pub extern "C" fn get_price_of(etf_id_ptr: *const c_char) -> f64 {
-    let etf_id = unsafe {
-        CStr::from_ptr(etf_id_ptr)
-    }.to_str().expect("could not parse C string as utf-8");
- 
-    let result = RT.block_on(yahoo_finance_info::get_price_of(&etf_id.to_string()));
-    check_result(result, || f64::NAN, |price| price)
+pub extern "C" fn etfplan_shutdown() -> CErrorCode {
+    report_code(catch_panic(|| lock_db().take().map(drop).ok_or(Error::NotInitialized)))
 }
 
-fn get_settings_from_db() -> Result<Settings, SqliteError> {
-    let db: std::sync::MutexGuard<'_, Database> = DB.lock().unwrap();
+// Replaces the provider every price and etf lookup goes through, which is Yahoo Finance by default.
+pub fn set_price_provider(provider: Arc<dyn PriceProvider>) {
+    *PRICE_PROVIDER.write().unwrap_or_else(PoisonError::into_inner) = Some(provider);
+}
 
-    let budget = db.get_budget()?.expect("database should always have a budget");
-    let etf_settings = db
-        .get_all_etfs()?
-        .map(|etf| 
-            etf.map(|etf| EtfSetting::new(etf.id, etf.isin, etf.name, etf.proportion, etf.cumulative))
-        ).collect::<Result<Vec<_>, _>>()?;
-    Ok(Settings::new(budget, etf_settings))
+fn price_provider() -> Result<Arc<dyn PriceProvider>, Error> {
+    if let Some(provider) = PRICE_PROVIDER.read().unwrap_or_else(PoisonError::into_inner).as_ref() {
+        return Ok(provider.clone());
+    }
+    let provider: Arc<dyn PriceProvider> = Arc::new(YahooPriceProvider::new()?);
+    set_price_provider(provider.clone());
+    Ok(provider)
 }
 
-async fn get_prices(settings: &Settings) -> Result<Vec<f64>, YahooError> {
-    let futures = settings.etf_settings.iter().map(|etf| yahoo_finance_info::get_price_of(&etf.id));
-    future::try_join_all(futures).await
+// A fixtures file that cannot be read is a wrong path rather than a network error.
+pub fn read_price_fixtures(path: &str) -> Result<FixturePriceProvider, Error> {
+    let json = std::fs::read_to_string(path).map_err(|e| Error::InvalidArgument(format!("could not read {path}: {e}")))?;
+    Ok(FixturePriceProvider::from_json(&json)?)
 }
 
 #[no_mangle]
-pub extern "C" fn suggest_investments() -> CInvestments {    
-    let settings = match get_settings_from_db() {
-        Err(e) => {
-            eprintln!("{e}");
-            return CInvestments::new(std::ptr::null(), 0);
-        }
-        Ok(settings) => settings
-    };
+pub extern "C" fn etfplan_use_price_fixtures(fixtures_path_ptr: *const c_char) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let fixtures_path = c_char_ptr_to_string(fixtures_path_ptr)?;
+        set_price_provider(Arc::new(read_price_fixtures(&fixtures_path)?));
+        Ok(())
+    }))
+}
+
+// Negative values keep the default of 15 minutes before refreshing and 3 days of maximum age respectively.
+#[no_mangle]
+pub extern "C" fn etfplan_set_price_cache_policy(refresh_after_seconds: i64, max_age_seconds: i64) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let default = PriceCachePolicy::default();
+        let refresh_after_seconds = if refresh_after_seconds < 0 { default.refresh_after_seconds } else { refresh_after_seconds };
+        let max_age_seconds = if max_age_seconds < 0 { default.max_age_seconds } else { max_age_seconds };
+        prices::set_price_cache_policy(PriceCachePolicy::new(refresh_after_seconds, max_age_seconds));
+        Ok(())
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_use_yahoo_prices() -> CErrorCode {
+    report_code(catch_panic(|| {
+        set_price_provider(Arc::new(YahooPriceProvider::new()?));
+        Ok(())
+    }))
+}
+
+// The one etf the provider knows by this isin.
+pub fn find_etf(etf_isin: &str) -> Result<ETF, Error> {
+    let xs = RT.block_on(price_provider()?.search_by_isin(&etf_isin.to_string()))?;
+    if xs.len() > 1 {
+        return Err(Error::AmbiguousIsin(etf_isin.to_string()));
+    }
+    xs.into_iter().next().ok_or(Error::EtfNotFound(etf_isin.to_string()))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_search_etf_info(etf_isin_ptr: *const c_char) -> *const CEtfInfo {
+    let result = catch_panic(|| {
+        let etf_isin = c_char_ptr_to_string(etf_isin_ptr)?;
+        let x = find_etf(&etf_isin)?;
+        let etf_info = CEtfInfo::new(string_to_c_char_ptr(x.ticker), string_to_c_char_ptr(x.name), string_to_c_char_ptr(x.isin));
+        Ok(Box::into_raw(Box::new(etf_info)) as *const CEtfInfo)
+    });
+    report(result).unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_get_price_of(etf_id_ptr: *const c_char) -> f64 {
+    let result = catch_panic(|| {
+        let etf_id = c_char_ptr_to_string(etf_id_ptr)?;
+        Ok(RT.block_on(price_provider()?.latest_price(&etf_id))?)
+    });
+    report(result).unwrap_or(f64::NAN)
+}
+
+pub fn get_settings_from_db() -> Result<Settings, Error> {
+    with_db(|db| {
+        let budget = db.get_budget()?.ok_or(Error::NoBudget)?;
+        let etf_settings = db
+            .get_all_etfs()?
+            .map(|etf| 
+                etf.map(|etf| EtfSetting::new(etf.id, etf.isin, etf.name, etf.proportion, etf.cumulative)
+                    .with_fee(Fee::new(etf.fee_fixed, etf.fee_basis_points))
+                    .with_limits(OrderLimits::new(etf.min_quantity, etf.max_quantity, etf.lot_size, etf.min_order_amount)))
+            ).collect::<Result<Vec<_>, _>>()?;
+        Ok(Settings::new(budget, etf_settings))
+    })
+}
+
+// The prices of the tickers in the budget currency, in its major unit, going through the price cache.
+pub fn get_prices(tickers: &[String]) -> Result<Vec<CachedPrice>, Error> {
+    let currency = with_db(|db| Ok(db.get_currency()?))?.unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+    let provider = price_provider()?;
+    let cached_prices = get_cached_prices(provider.as_ref(), tickers)?;
+    convert_prices(provider.as_ref(), cached_prices, &currency)
+}
 
-    let prices = RT.block_on(get_prices(&settings));
-    let prices = match prices {
-        Err(e) => {
-            eprintln!("{e}");
-            return CInvestments::new(std::ptr::null(), 0);
+// The suggested investments together with the settings and prices in cents they were computed from.
+pub struct Plan {
+    pub settings: Settings,
+    pub prices: Vec<f64>,
+    pub investments: Vec<(Investment, CachedPrice)>,
+}
+
+pub fn suggest() -> Result<Plan, Error> {
+    let settings = get_settings_from_db()?;
+    if settings.budget <= 0 {
+        return Err(Error::NoBudget);
+    }
+
+    let tickers = settings.etf_settings.iter().map(|etf| etf.id.clone()).collect::<Vec<_>>();
+    let cached_prices = get_prices(&tickers)?;
+    let prices = cached_prices.iter()
+        .map(|p| p.price * 100.0 /* convert to cents */)
+        .collect::<Vec<_>>();
+    if let Some((etf, _)) = settings.etf_settings.iter().zip(&prices).find(|(_, price)| price.is_nan() || **price <= 0.0) {
+        return Err(Error::Parse(format!("a non-positive price for {}", etf.id)));
+    }
+
+    // The shares held value the etfs at the market price, and bound what rebalancing can sell.
+    let shares = with_db(|db| Ok(db.shares_held()?))?;
+    let etf_settings = settings.etf_settings.into_iter()
+        .map(|etf| {
+            let held = shares.get(&etf.id).copied().unwrap_or(0.0);
+            etf.with_shares(held)
+        })
+        .collect();
+    let settings = Settings::new(settings.budget, etf_settings);
+    let settings = if with_db(|db| Ok(db.get_target_market_value()?))? {
+        // An etf without purchases in the ledger has no shares to value, so it keeps the amount entered for it.
+        let valued = settings.clone().valued_at(Valuation::MarketValue, &prices);
+        let etf_settings = settings.etf_settings.into_iter().zip(valued.etf_settings)
+            .map(|(etf, valued)| if shares.contains_key(&etf.id) { valued } else { etf })
+            .collect();
+        Settings::new(settings.budget, etf_settings)
+    } else {
+        settings
+    };
+    let allocation = match with_db(|db| Ok(db.get_fractional_decimals()?))? {
+        Some(decimals) => Allocation::Fractional { decimals: decimals as u32 },
+        None => Allocation::WholeShares,
+    };
+    let investments = match with_db(|db| Ok(db.get_rebalancing()?))? {
+        Some(r) => {
+            let rebalancing = Rebalancing::new(r.max_turnover_basis_points, Fee::new(r.sell_fee_fixed, r.sell_fee_basis_points));
+            investment_planner::rebalance_investments(rebalancing, allocation, settings.clone(), &prices)
         }
-        Ok(prices) => prices.into_iter().map(|p: f64| p * 100.0 /* convert euros to cents */).collect::<Vec<_>>() 
+        None => investment_planner::next_investments_with(allocation, settings.clone(), &prices),
     };
-    
-    let xs = investment_planner::next_investments(settings, &prices);
-    CInvestments::from(xs)
+    // Sells come after the buys, so look the prices up by etf.
+    let investments = investments.into_iter().map(|investment| {
+        let i = tickers.iter().position(|ticker| *ticker == investment.etf_id).expect("investments are in configured etfs");
+        (investment, cached_prices[i].clone())
+    }).collect();
+    Ok(Plan { settings, prices, investments })
 }
 
 #[no_mangle]
-pub extern "C" fn persist_settings(settings: *const CSettings) -> i64 {
-    let settings = unsafe {&*settings};
-    let settings = settings.settings();
-    
-    let db = DB.lock().unwrap();
-    if let Err(e) = db.set_budget(settings.budget) {
-        eprintln!("{e}");
-        return  -1;
-    }
-    match db.get_all_etfs() {
-        Err(e) => {
-            eprintln!("{e}");
-            return  -2;
-        }
-        Ok(etfs) => {
-            for etf in etfs {
-                match etf {
-                    Err(e) => {
-                        eprintln!("{e}");
-                        return  -3;
-                    }
-                    Ok(etf) => {
-                        if let Err(e) = db.remove_etf(etf.id) {
-                            eprintln!("{e}");
-                            return  -4;
-                        }
-                    }
+pub extern "C" fn etfplan_suggest_investments() -> CInvestments {    
+    report(catch_panic(|| suggest().map(|plan| CInvestments::from(plan.investments)))).unwrap_or(CInvestments::new(std::ptr::null(), 0, 0))
+}
+
+// Like etfplan_suggest_investments, with each investment explained by how its etf drifts from the ideal proportions.
+#[no_mangle]
+pub extern "C" fn etfplan_suggest_investments_explained() -> CSuggestions {
+    report(catch_panic(|| suggest().map(CSuggestions::from))).unwrap_or(CSuggestions::new(std::ptr::null(), 0, 0, 0))
+}
+
+fn save_settings(settings: *const CSettings) -> Result<(), Error> {
+    if settings.is_null() {
+        return Err(Error::InvalidArgument("settings is null".to_string()));
+    }
+    store_settings(unsafe {&*settings}.settings()?)
+}
+
+// Replaces the budget and all etfs in the database.
+pub fn store_settings(settings: Settings) -> Result<(), Error> {
+    let etfs = settings.etf_settings
+        .into_iter()
+        .map(|etf| EtfData::new(etf.id, etf.isin, etf.name, etf.ideal_proportion, etf.cumulative)
+            .with_fee(etf.fee.fixed, etf.fee.basis_points)
+            .with_limits(etf.limits.min_quantity, etf.limits.max_quantity, etf.limits.lot_size, etf.limits.min_amount))
+        .collect::<Vec<EtfData>>();
+    with_db(|db| db.transaction(|db| {
+        // The cumulative amount of an etf with purchases comes from the ledger, so settings read before a purchase
+        // was recorded cannot change it.
+        let purchased = db.list_purchases()?;
+        for etf in &etfs {
+            if let Some(current) = db.get_etf(&etf.id)?.filter(|current| current.cumulative != etf.cumulative) {
+                if purchased.iter().any(|purchase| purchase.etf_id == etf.id) {
+                    return Err(Error::InvalidArgument(format!(
+                        "the cumulative amount of {} is {} from its purchases, not {}", etf.id, current.cumulative, etf.cumulative,
+                    )));
                 }
             }
         }
+        Ok(db.replace_settings(settings.budget, etfs)?)
+    }))
+}
+
+/// Fails with InvalidArgument, changing nothing, when an etf with purchases is given another cumulative amount than its ledger adds up to.
+#[no_mangle]
+pub extern "C" fn etfplan_persist_settings(settings: *const CSettings) -> CErrorCode {
+    report_code(catch_panic(|| save_settings(settings)))
+}
+
+// Records the investments as bought or sold today, all or none of them.
+pub fn record_investments(investments: &[Investment]) -> Result<(), Error> {
+    let date = chrono::Local::now().date_naive().format("%Y-%m-%d").to_string();
+
+    with_db(|db| db.transaction(|db| {
+        for investment in investments.iter().filter(|investment| investment.quantity > 0) {
+            if investment.decimals > MAX_DECIMALS {
+                return Err(Error::InvalidArgument(format!("quantities have at most {MAX_DECIMALS} decimals")));
+            }
+            if db.get_etf(&investment.etf_id)?.is_none() {
+                return Err(Error::InvalidArgument(format!("{} is not one of the configured etfs", investment.etf_id)));
+            }
+            // A sell goes into the ledger as a negative purchase.
+            let quantity = match investment.action {
+                Action::Buy => investment.quantity,
+                Action::Sell => -investment.quantity,
+            };
+            db.record_fractional_purchase(&investment.etf_id, quantity, investment.decimals, investment.price, investment.fee, &date)?;
+        }
+        Ok(())
+    }))
+}
+
+fn confirm(investments: &CInvestments) -> Result<(), Error> {
+    if investments.length > 0 && investments.investments.is_null() {
+        return Err(Error::InvalidArgument("investments is null".to_string()));
     }
-    for etf in settings.etf_settings {
-        if let Err(e) = db.add_etf(EtfData::new(etf.id, etf.isin, etf.name, etf.ideal_proportion, etf.cumulative)) {
-            eprintln!("{e}");
-            return -1;
+
+    let mut purchases = vec![];
+    for i in 0..investments.length {
+        let investment = unsafe { &*investments.investments.add(i) };
+        if investment.quantity > 0 {
+            let action = match CInvestmentAction::try_from(investment.action)? {
+                CInvestmentAction::Buy => Action::Buy,
+                CInvestmentAction::Sell => Action::Sell,
+            };
+            purchases.push(Investment {
+                fee: investment.fee,
+                decimals: investment.quantity_decimals,
+                action,
+                ..Investment::new(c_char_ptr_to_string(investment.etf_id)?, String::new(), investment.quantity, investment.price)
+            });
         }
     }
+    record_investments(&purchases)
+}
+
+fn parse_currency(currency_ptr: *const c_char) -> Result<String, Error> {
+    let currency = c_char_ptr_to_string(currency_ptr)?;
+    if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(Error::InvalidArgument(format!("{currency} is not a three letter currency code")));
+    }
+    Ok(currency.to_ascii_uppercase())
+}
+
+// The currency of the budget, which all prices are converted into before suggesting investments.
+#[no_mangle]
+pub extern "C" fn etfplan_set_budget_currency(currency_ptr: *const c_char) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let currency = parse_currency(currency_ptr)?;
+        with_db(|db| Ok(db.set_currency(&currency)?))
+    }))
+}
 
-    return 0;
+// The returned string must be released with etfplan_free_string.
+#[no_mangle]
+pub extern "C" fn etfplan_get_budget_currency() -> *const c_char {
+    let result = catch_panic(|| {
+        let currency = with_db(|db| Ok(db.get_currency()?))?.unwrap_or_else(|| DEFAULT_CURRENCY.to_string());
+        Ok(string_to_c_char_ptr(currency))
+    });
+    report(result).unwrap_or(std::ptr::null())
 }
 
+// Lets suggestions buy fractions of shares down to `decimals` decimal places, or only whole shares when negative.
 #[no_mangle]
-pub extern "C" fn get_settings() -> *const CSettings {
-    let settings = match get_settings_from_db() {
-        Err(e) => {
-            eprintln!("{e}");
-            return std::ptr::null();
+pub extern "C" fn etfplan_set_fractional_shares(decimals: i32) -> CErrorCode {
+    report_code(catch_panic(|| {
+        if decimals > MAX_DECIMALS as i32 {
+            return Err(Error::InvalidArgument(format!("fractional shares have at most {MAX_DECIMALS} decimals")));
         }
-        Ok(settings) => settings
-    };
+        let decimals = (decimals >= 0).then_some(decimals as i64);
+        with_db(|db| Ok(db.set_fractional_decimals(decimals)?))
+    }))
+}
 
-    Box::into_raw(Box::new(CSettings::from(settings)))
-}
\ No newline at end of file
+// The decimals set with etfplan_set_fractional_shares, or -1 for whole shares and on errors.
+#[no_mangle]
+pub extern "C" fn etfplan_get_fractional_shares() -> i32 {
+    let result = catch_panic(|| Ok(with_db(|db| Ok(db.get_fractional_decimals()?))?.map_or(-1, |decimals| decimals as i32)));
+    report(result).unwrap_or(-1)
+}
+
+// Whether suggestions aim at the ideal proportions of what was put in, or of what the holdings are worth
+// now: the shares in the purchase ledger times the latest price, or the amount entered for an etf without
+// purchases. The valuation is a CValuation, passed as an integer so that values outside the enum are
+// rejected rather than undefined behavior.
+#[no_mangle]
+pub extern "C" fn etfplan_set_valuation(valuation: i32) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let valuation = Valuation::from(CValuation::try_from(valuation)?);
+        with_db(|db| Ok(db.set_target_market_value(valuation == Valuation::MarketValue)?))
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_get_valuation() -> CValuation {
+    let result = catch_panic(|| {
+        let target_market_value = with_db(|db| Ok(db.get_target_market_value()?))?;
+        Ok(if target_market_value { CValuation::MarketValue } else { CValuation::CostBasis })
+    });
+    report(result).unwrap_or(CValuation::CostBasis)
+}
+
+// Lets suggestions sell etfs above their ideal proportion, see CRebalancing.
+#[no_mangle]
+pub extern "C" fn etfplan_set_rebalancing(rebalancing: CRebalancing) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let rebalancing = rebalancing.rebalancing()?;
+        with_db(|db| Ok(db.set_rebalancing(rebalancing)?))
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_get_rebalancing() -> CRebalancing {
+    let result = catch_panic(|| Ok(CRebalancing::from(with_db(|db| Ok(db.get_rebalancing()?))?)));
+    report(result).unwrap_or(CRebalancing::from(None))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_confirm_investments(investments: CInvestments) -> CErrorCode {
+    report_code(catch_panic(|| confirm(&investments)))
+}
+
+pub fn fetch_history(ticker: String, start: i64, end: i64, interval: Interval) -> Result<(), Error> {
+    let history = RT.block_on(price_provider()?.history(&ticker, start, end, interval))?;
+
+    let bars = history.bars
+        .into_iter()
+        .map(|bar| PriceBarData::new(bar.timestamp, bar.open, bar.high, bar.low, bar.close, bar.adj_close, bar.volume as i64))
+        .collect::<Vec<_>>();
+    let dividends = history.dividends
+        .into_iter()
+        .map(|dividend| DividendData::new(dividend.timestamp, dividend.amount))
+        .collect::<Vec<_>>();
+    with_db(|db| db.transaction(|db| {
+        db.store_price_bars(&ticker, interval.as_str(), &bars)?;
+        db.store_dividends(&ticker, &dividends)?;
+        Ok(())
+    }))
+}
+
+// Downloads the bars and dividends between start and end (unix seconds, inclusive) into the database. The
+// interval is a CInterval.
+#[no_mangle]
+pub extern "C" fn etfplan_fetch_price_history(ticker_ptr: *const c_char, start: i64, end: i64, interval: i32) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let interval = Interval::from(CInterval::try_from(interval)?);
+        fetch_history(c_char_ptr_to_string(ticker_ptr)?, start, end, interval)
+    }))
+}
+
+// Reads the bars stored by etfplan_fetch_price_history, without going to the price provider.
+#[no_mangle]
+pub extern "C" fn etfplan_get_price_history(ticker_ptr: *const c_char, start: i64, end: i64, interval: i32) -> CPriceBars {
+    let result = catch_panic(|| {
+        let ticker = c_char_ptr_to_string(ticker_ptr)?;
+        let interval = Interval::from(CInterval::try_from(interval)?);
+        with_db(|db| Ok(db.get_price_bars(&ticker, interval.as_str(), start, end)?))
+    });
+    report(result.map(CPriceBars::from)).unwrap_or(CPriceBars::new(std::ptr::null(), 0))
+}
+
+// The settings, purchases and cached prices as a versioned document, to back the plan up or move it to another
+// machine, in the CPlanFormat format. The returned string must be released with etfplan_free_string.
+#[no_mangle]
+pub extern "C" fn etfplan_export_plan(format: i32) -> *const c_char {
+    let result = catch_panic(|| {
+        let format = PlanFormat::from(CPlanFormat::try_from(format)?);
+        with_db(|db| Ok(db.export_plan(format)?)).map(string_to_c_char_ptr)
+    });
+    report(result).unwrap_or(std::ptr::null())
+}
+
+// Replaces the settings, purchases and cached prices with those of a document from etfplan_export_plan. Nothing changes
+// when the document has another version or proportions that are negative or add up to 0.
+#[no_mangle]
+pub extern "C" fn etfplan_import_plan(document_ptr: *const c_char, format: i32) -> CErrorCode {
+    report_code(catch_panic(|| {
+        let document = c_char_ptr_to_string(document_ptr)?;
+        let format = PlanFormat::from(CPlanFormat::try_from(format)?);
+        with_db(|db| Ok(db.import_plan(&document, format).map(drop)?))
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_get_settings() -> *const CSettings {
+    let result = catch_panic(|| {
+        let settings = get_settings_from_db()?;
+        Ok(Box::into_raw(Box::new(CSettings::from(settings))) as *const CSettings)
+    });
+    report(result).unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_string(string: *const c_char) {
+    report(catch_panic(|| {
+        unsafe { free_c_char_ptr(string) };
+        Ok(())
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_etf_info(etf_info: *const CEtfInfo) {
+    if etf_info.is_null() {
+        return;
+    }
+    report(catch_panic(|| {
+        unsafe {
+            Box::from_raw(etf_info as *mut CEtfInfo).free();
+        }
+        Ok(())
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_investments(investments: CInvestments) {
+    report(catch_panic(|| {
+        unsafe {
+            for investment in c_array_to_vec(investments.investments, investments.length) {
+                investment.free();
+            }
+        }
+        Ok(())
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_suggestions(suggestions: CSuggestions) {
+    report(catch_panic(|| {
+        unsafe {
+            for suggestion in c_array_to_vec(suggestions.suggestions, suggestions.length) {
+                suggestion.investment.free();
+            }
+        }
+        Ok(())
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_price_bars(bars: CPriceBars) {
+    report(catch_panic(|| {
+        drop(unsafe { c_array_to_vec(bars.bars, bars.length) });
+        Ok(())
+    }));
+}
+
+#[no_mangle]
+pub extern "C" fn etfplan_free_settings(settings: *const CSettings) {
+    if settings.is_null() {
+        return;
+    }
+    report(catch_panic(|| {
+        unsafe {
+            let settings = Box::from_raw(settings as *mut CSettings);
+            for etf_setting in c_array_to_vec(settings.etf_settings, settings.num_etf_settings) {
+                etf_setting.free();
+            }
+        }
+        Ok(())
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yahoo_finance_info::{Quote, YahooError};
+
+    // Serializes the tests that initialize the global database or price provider.
+    pub(crate) static GLOBALS: Mutex<()> = Mutex::new(());
+
+    pub(crate) fn temp_db_path(name: &str) -> CString {
+        let path = std::env::temp_dir().join(format!("etf-investment-plan-ffi-{name}-db"));
+        let _ = std::fs::remove_file(&path);
+        CString::new(path.to_str().unwrap()).unwrap()
+    }
+
+    // The round trips only touch the conversions and free functions. The leak check workflow runs them under
+    // LeakSanitizer: `RUSTFLAGS=-Zsanitizer=leak cargo +nightly test --lib --target x86_64-unknown-linux-gnu round_trip`.
+
+    fn etf_settings() -> Vec<EtfSetting> {
+        vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.7, 10_000),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.3, 5_000),
+        ]
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let settings = Settings::new(50_000, etf_settings());
+        let c_settings = Box::into_raw(Box::new(CSettings::from(settings.clone())));
+
+        assert_eq!(unsafe { &*c_settings }.settings().unwrap(), settings);
+        etfplan_free_settings(c_settings);
+    }
+
+    #[test]
+    fn test_settings_with_limits_round_trip() {
+        let etf_settings = etf_settings().into_iter()
+            .map(|etf| etf.with_limits(OrderLimits::new(2, Some(10), 2, 5_000)))
+            .collect();
+        let settings = Settings::new(50_000, etf_settings);
+        let c_settings = Box::into_raw(Box::new(CSettings::from(settings.clone())));
+
+        assert_eq!(unsafe { &*c_settings }.settings().unwrap(), settings);
+        etfplan_free_settings(c_settings);
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(Error::Yahoo(YahooError::EmptyDataSet).code(), CErrorCode::EtfNotFound);
+        assert_eq!(Error::Yahoo(YahooError::FetchFailed("timeout".into())).code(), CErrorCode::YahooNetwork);
+        assert_eq!(Error::Yahoo(YahooError::InvalidJson).code(), CErrorCode::Parse);
+    }
+
+    #[test]
+    fn test_zeroed_etf_setting_has_no_limits() {
+        let etf_setting = CEtfSetting::new(c"IUSE.L".as_ptr(), c"IE00B3ZW0K18".as_ptr(), c"NAME".as_ptr(), 1.0, 0, 0, 0, 0, false, 0, 0, 0);
+        assert_eq!(etf_setting.etf_setting().unwrap().limits, OrderLimits::default());
+
+        let etf_setting = CEtfSetting { lot_size: -1, ..etf_setting };
+        assert!(matches!(etf_setting.etf_setting(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_free_settings_without_etfs() {
+        let c_settings = Box::into_raw(Box::new(CSettings::from(Settings::new(0, vec![]))));
+        etfplan_free_settings(c_settings);
+        etfplan_free_settings(std::ptr::null());
+    }
+
+    #[test]
+    fn test_investments_round_trip() {
+        let investments = vec![
+            (Investment::new("IUSE.L".into(), "iShares S&P 500 EUR Hedged".into(), 3, 11_000), CachedPrice::new(110.0, None, 1_700_000_000, false)),
+            (Investment::new("AGGG.L".into(), "iShares Core Global Aggregate Bond".into(), 0, 500), CachedPrice::new(5.0, None, 1_700_000_000, true)),
+        ];
+        let c_investments = CInvestments::from(investments);
+
+        assert_eq!(c_investments.length, 2);
+        let first = unsafe { &*c_investments.investments };
+        assert_eq!(c_char_ptr_to_string(first.etf_id).unwrap(), "IUSE.L");
+        assert_eq!(first.quantity, 3);
+        assert_eq!(first.price_fetched_at, 1_700_000_000);
+        assert!(!first.price_is_stale);
+        etfplan_free_investments(c_investments);
+    }
+
+    #[test]
+    fn test_free_empty_investments() {
+        etfplan_free_investments(CInvestments::from(vec![]));
+        etfplan_free_investments(CInvestments::new(std::ptr::null(), 0, 0));
+    }
+
+    #[test]
+    fn test_free_etf_info() {
+        let etf_info = CEtfInfo::new(string_to_c_char_ptr("IUSE.L".into()), string_to_c_char_ptr("iShares S&P 500 EUR Hedged".into()), string_to_c_char_ptr("IE00B3ZW0K18".into()));
+        etfplan_free_etf_info(Box::into_raw(Box::new(etf_info)));
+        etfplan_free_etf_info(std::ptr::null());
+    }
+
+    #[test]
+    fn test_persist_null_settings_reports_error() {
+        assert_eq!(etfplan_persist_settings(std::ptr::null()), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_last_error_code(), CErrorCode::InvalidArgument);
+        let message = unsafe { CStr::from_ptr(etfplan_last_error_message()) }.to_str().unwrap();
+        assert_eq!(message, "invalid argument: settings is null");
+    }
+
+    #[test]
+    fn test_non_utf8_etf_setting_reports_parse_error() {
+        let invalid = CString::new(vec![0xff, 0xfe]).unwrap();
+        let etf_setting = CEtfSetting::new(invalid.as_ptr(), invalid.as_ptr(), invalid.as_ptr(), 1.0, 0, 0, 0, 0, false, 0, 1, 0);
+        let settings = CSettings::new(100, &etf_setting, 1);
+
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Parse);
+        assert!(!etfplan_last_error_message().is_null());
+    }
+
+    #[test]
+    fn test_successful_call_clears_last_error() {
+        assert!(report::<()>(Err(Error::NoBudget)).is_none());
+        assert_eq!(etfplan_last_error_code(), CErrorCode::NoBudget);
+        assert_eq!(report(Ok(())), Some(()));
+        assert_eq!(etfplan_last_error_code(), CErrorCode::Ok);
+        assert!(etfplan_last_error_message().is_null());
+    }
+
+    #[test]
+    fn test_panic_becomes_error_code() {
+        let result = catch_panic::<()>(|| panic!("boom"));
+        assert!(report(result).is_none());
+        assert_eq!(etfplan_last_error_code(), CErrorCode::Panic);
+        let message = unsafe { CStr::from_ptr(etfplan_last_error_message()) }.to_str().unwrap();
+        assert_eq!(message, "internal error: boom");
+    }
+
+    #[test]
+    fn test_db_lock_recovers_from_poisoning() {
+        let _ = std::thread::spawn(|| {
+            let _db = lock_db();
+            panic!("poison the database mutex");
+        }).join();
+
+        assert!(DB.is_poisoned());
+        let _db = lock_db();
+    }
+
+    #[test]
+    fn test_suggest_investments_with_fixture_prices() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("suggest");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let fixtures = CString::new(concat!(env!("CARGO_MANIFEST_DIR"), "/yahoo-finance-info/fixtures/prices.json")).unwrap();
+        assert_eq!(etfplan_use_price_fixtures(fixtures.as_ptr()), CErrorCode::Ok);
+
+        let etf_info = etfplan_search_etf_info(c"IE00B3ZW0K18".as_ptr());
+        assert_eq!(c_char_ptr_to_string(unsafe { &*etf_info }.id).unwrap(), "IUSE.L");
+        etfplan_free_etf_info(etf_info);
+
+        let settings = CSettings::from(Settings::new(50_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 0),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 0),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        let investments = etfplan_suggest_investments();
+        assert_eq!(etfplan_last_error_code(), CErrorCode::Ok);
+        let quantities = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+            .iter()
+            .map(|investment| (c_char_ptr_to_string(investment.etf_id).unwrap(), investment.quantity, investment.price))
+            .collect::<Vec<_>>();
+        assert_eq!(quantities, vec![("IUSE.L".to_string(), 2, 11_236), ("AGGG.L".to_string(), 51, 487)]);
+        etfplan_free_investments(investments);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    fn suggested_prices() -> Vec<(i64, bool)> {
+        let investments = etfplan_suggest_investments();
+        let prices = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+            .iter()
+            .map(|investment| (investment.price, investment.price_is_stale))
+            .collect();
+        etfplan_free_investments(investments);
+        prices
+    }
+
+    #[test]
+    fn test_suggest_investments_falls_back_to_cached_prices() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("price-cache");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(50_000, etf_settings()));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 112.36).with_price("AGGG.L", 4.87)));
+        assert_eq!(suggested_prices(), vec![(11_236, false), (487, false)]);
+
+        // Fresh enough cached prices are used without asking the provider, which knows nothing now.
+        set_price_provider(Arc::new(FixturePriceProvider::new()));
+        assert_eq!(suggested_prices(), vec![(11_236, false), (487, false)]);
+
+        // Once they need refreshing, a failing provider falls back to the cache and marks the prices stale.
+        assert_eq!(etfplan_set_price_cache_policy(0, -1), CErrorCode::Ok);
+        assert_eq!(suggested_prices(), vec![(11_236, true), (487, true)]);
+
+        // A provider that answers again replaces the cached price.
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 120.0)));
+        assert_eq!(suggested_prices(), vec![(12_000, false), (487, true)]);
+
+        // Prices that are too old are not used at all.
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        assert_eq!(etfplan_set_price_cache_policy(0, 0), CErrorCode::Ok);
+        let investments = etfplan_suggest_investments();
+        assert!(investments.investments.is_null());
+        assert_eq!(etfplan_last_error_code(), CErrorCode::EtfNotFound);
+
+        assert_eq!(etfplan_set_price_cache_policy(-1, -1), CErrorCode::Ok);
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_fetch_price_history_with_fixture_prices() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("history");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let fixtures = CString::new(concat!(env!("CARGO_MANIFEST_DIR"), "/yahoo-finance-info/fixtures/prices.json")).unwrap();
+        assert_eq!(etfplan_use_price_fixtures(c"/nonexistent/prices.json".as_ptr()), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_use_price_fixtures(fixtures.as_ptr()), CErrorCode::Ok);
+
+        assert_eq!(etfplan_fetch_price_history(c"AGGG.L".as_ptr(), 1_735_776_000, 1_736_208_000, CInterval::Day as i32), CErrorCode::Ok);
+        assert_eq!(etfplan_fetch_price_history(c"IWDA.AS".as_ptr(), 1_735_776_000, 1_736_208_000, CInterval::Day as i32), CErrorCode::EtfNotFound);
+
+        let bars = etfplan_get_price_history(c"AGGG.L".as_ptr(), 1_735_862_400, 1_736_208_000, CInterval::Day as i32);
+        let closes = unsafe { std::slice::from_raw_parts(bars.bars, bars.length) }
+            .iter()
+            .map(|bar| (bar.timestamp, bar.close, bar.adj_close))
+            .collect::<Vec<_>>();
+        assert_eq!(closes, vec![(1_735_862_400, 4.87, 4.82), (1_736_121_600, 4.82, 4.82), (1_736_208_000, 4.87, 4.87)]);
+        etfplan_free_price_bars(bars);
+
+        assert_eq!(etfplan_fetch_price_history(c"AGGG.L".as_ptr(), 1_735_776_000, 1_736_208_000, 3), CErrorCode::InvalidArgument);
+        let weekly = etfplan_get_price_history(c"AGGG.L".as_ptr(), 0, i64::MAX, CInterval::Week as i32);
+        assert_eq!(weekly.length, 0);
+        etfplan_free_price_bars(weekly);
+        let dividends = with_db(|db| Ok(db.get_dividends("AGGG.L", 0, i64::MAX)?)).unwrap();
+        assert_eq!(dividends, vec![DividendData::new(1_736_121_600, 0.05)]);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_investments_converts_prices_into_budget_currency() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("currency");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(50_000, etf_settings()));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        let currency = etfplan_get_budget_currency();
+        assert_eq!(c_char_ptr_to_string(currency).unwrap(), "EUR");
+        etfplan_free_string(currency);
+        assert_eq!(etfplan_set_budget_currency(c"euro".as_ptr()), CErrorCode::InvalidArgument);
+
+        set_price_provider(Arc::new(FixturePriceProvider::new()
+            .with_quote("IUSE.L", Quote::new(9_442.0, Some("GBp".into())))
+            .with_quote("AGGG.L", Quote::new(5.25, Some("USD".into())))
+            .with_price("GBPEUR=X", 1.19)
+            .with_price("USDEUR=X", 0.92)
+            .with_price("EURGBP=X", 0.84)));
+        assert_eq!(suggested_prices(), vec![(11_235, false), (483, false)]);
+
+        assert_eq!(etfplan_set_budget_currency(c"gbp".as_ptr()), CErrorCode::Ok);
+        let currency = etfplan_get_budget_currency();
+        assert_eq!(c_char_ptr_to_string(currency).unwrap(), "GBP");
+        etfplan_free_string(currency);
+        // There is no USD to GBP rate, so nothing can be suggested.
+        let investments = etfplan_suggest_investments();
+        assert!(investments.investments.is_null());
+        assert_eq!(etfplan_last_error_code(), CErrorCode::EtfNotFound);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_and_confirm_investments_with_fees() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("fees");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let fee = Fee::new(4_00, 0);
+        let settings = CSettings::from(Settings::new(1_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 0).with_fee(fee),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 0).with_fee(fee),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        let c_settings = etfplan_get_settings();
+        assert_eq!(unsafe { &*c_settings }.settings().unwrap().etf_settings[1].fee, fee);
+        etfplan_free_settings(c_settings);
+
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 1.0).with_price("AGGG.L", 1.0)));
+        let investments = etfplan_suggest_investments();
+        let quantities = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+            .iter()
+            .map(|investment| (investment.quantity, investment.fee))
+            .collect::<Vec<_>>();
+        // A second order would cost as much in fees as it could buy.
+        assert_eq!(quantities, vec![(5, 400), (0, 0)]);
+        assert_eq!(investments.total_fees, 400);
+
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(investments.investments, investments.length, investments.total_fees)), CErrorCode::Ok);
+        let purchases = with_db(|db| Ok(db.list_purchases()?)).unwrap();
+        assert_eq!(purchases.iter().map(|purchase| purchase.fees).collect::<Vec<_>>(), vec![400]);
+        etfplan_free_investments(investments);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_confirm_investments() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("confirm");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(1000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 10000),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 2000),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+
+        let ids = ["IUSE.L", "AGGG.L", "IWDA.AS"].map(|id| CString::new(id).unwrap());
+        let investment = |etf_id: &CString, quantity, price, action| CInvestment {
+            etf_id: etf_id.as_ptr(),
+            name: std::ptr::null(),
+            quantity,
+            price,
+            price_fetched_at: 0,
+            price_is_stale: false,
+            fee: 100,
+            quantity_decimals: 0,
+            action,
+        };
+        let mut investments = [
+            investment(&ids[0], 3, 1000, CInvestmentAction::Buy as i32),
+            investment(&ids[1], 1, 500, CInvestmentAction::Sell as i32),
+        ];
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(investments.as_mut_ptr(), investments.len(), 200)), CErrorCode::Ok);
+
+        // The settings from before the purchases would overwrite the amounts from the ledger.
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::InvalidArgument);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+        let c_settings = etfplan_get_settings();
+        let cumulative = unsafe { &*c_settings }.settings().unwrap().etf_settings.iter().map(|etf| etf.cumulative).collect::<Vec<_>>();
+        assert_eq!(cumulative, vec![13_000, 1_500]);
+        assert_eq!(etfplan_persist_settings(c_settings), CErrorCode::Ok);
+        etfplan_free_settings(c_settings);
+
+        let mut unknown = [investment(&ids[0], 1, 1000, CInvestmentAction::Buy as i32), investment(&ids[2], 1, 1000, CInvestmentAction::Buy as i32)];
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(unknown.as_mut_ptr(), unknown.len(), 200)), CErrorCode::InvalidArgument);
+        let mut invalid = [investment(&ids[0], 1, 1000, 2)];
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(invalid.as_mut_ptr(), invalid.len(), 100)), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(std::ptr::null_mut(), 1, 0)), CErrorCode::InvalidArgument);
+        assert_eq!(with_db(|db| Ok(db.list_purchases()?.len())).unwrap(), 2);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_and_confirm_fractional_investments() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("fractional");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(1_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 0),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 0),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        assert_eq!(etfplan_get_fractional_shares(), -1);
+        assert_eq!(etfplan_set_fractional_shares(MAX_DECIMALS as i32 + 1), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_set_fractional_shares(2), CErrorCode::Ok);
+        assert_eq!(etfplan_get_fractional_shares(), 2);
+
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 3.0).with_price("AGGG.L", 1.0)));
+        let investments = etfplan_suggest_investments();
+        let quantities = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+            .iter()
+            .map(|investment| (investment.quantity, investment.quantity_decimals))
+            .collect::<Vec<_>>();
+        assert_eq!(quantities, vec![(166, 2), (500, 2)]);
+
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(investments.investments, investments.length, investments.total_fees)), CErrorCode::Ok);
+        let cumulative = with_db(|db| Ok(db.get_all_etfs()?.map(|etf| etf.unwrap().cumulative).collect::<Vec<_>>())).unwrap();
+        assert_eq!(cumulative, vec![498, 500]);
+        etfplan_free_investments(investments);
+
+        assert_eq!(etfplan_set_fractional_shares(-1), CErrorCode::Ok);
+        assert_eq!(etfplan_get_fractional_shares(), -1);
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_and_confirm_rebalancing_sells() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("rebalancing");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(1_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 0),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 1_000),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+        // Only the shares in the ledger can be sold.
+        with_db(|db| Ok(db.record_purchase("IUSE.L", 30, 100, 0, "2026-01-05")?)).unwrap();
+
+        assert!(!etfplan_get_rebalancing().enabled);
+        assert_eq!(etfplan_set_rebalancing(CRebalancing::new(true, -1, 0, 0)), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_set_rebalancing(CRebalancing::new(true, 10_000, 0, 0)), CErrorCode::Ok);
+        assert_eq!(etfplan_get_rebalancing().max_turnover_basis_points, 10_000);
+
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 1.0).with_price("AGGG.L", 1.0)));
+        let investments = etfplan_suggest_investments();
+        let actions = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+            .iter()
+            .map(|investment| (c_char_ptr_to_string(investment.etf_id).unwrap(), investment.action, investment.quantity))
+            .collect::<Vec<_>>();
+        assert_eq!(actions, vec![
+            ("IUSE.L".to_string(), CInvestmentAction::Buy as i32, 0),
+            ("AGGG.L".to_string(), CInvestmentAction::Buy as i32, 15),
+            ("IUSE.L".to_string(), CInvestmentAction::Sell as i32, 5),
+        ]);
+
+        assert_eq!(etfplan_confirm_investments(CInvestments::new(investments.investments, investments.length, investments.total_fees)), CErrorCode::Ok);
+        let cumulative = with_db(|db| Ok(db.get_all_etfs()?.map(|etf| etf.unwrap().cumulative).collect::<Vec<_>>())).unwrap();
+        assert_eq!(cumulative, vec![2_500, 2_500]);
+        let purchases = with_db(|db| Ok(db.list_purchases()?)).unwrap();
+        assert_eq!(purchases.iter().map(|purchase| purchase.quantity).collect::<Vec<_>>(), vec![30, 15, -5]);
+        etfplan_free_investments(investments);
+
+        assert_eq!(etfplan_set_rebalancing(CRebalancing::new(false, 0, 0, 0)), CErrorCode::Ok);
+        assert!(!etfplan_get_rebalancing().enabled);
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_investments_at_market_value() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("market-value");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(1_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.5, 0),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.5, 0),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+        with_db(|db| {
+            db.record_purchase("IUSE.L", 10, 100, 0, "2026-01-05")?;
+            db.record_purchase("AGGG.L", 10, 100, 0, "2026-01-05")?;
+            Ok(())
+        }).unwrap();
+
+        // IUSE.L has doubled since it was bought.
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 2.0).with_price("AGGG.L", 1.0)));
+        let suggested_quantities = || {
+            let investments = etfplan_suggest_investments();
+            let quantities = unsafe { std::slice::from_raw_parts(investments.investments, investments.length) }
+                .iter()
+                .map(|investment| investment.quantity)
+                .collect::<Vec<_>>();
+            etfplan_free_investments(investments);
+            quantities
+        };
+        assert_eq!(etfplan_get_valuation(), CValuation::CostBasis);
+        assert_eq!(suggested_quantities(), vec![2, 5]);
+
+        assert_eq!(etfplan_set_valuation(2), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_set_valuation(CValuation::MarketValue as i32), CErrorCode::Ok);
+        assert_eq!(etfplan_get_valuation(), CValuation::MarketValue);
+        assert_eq!(suggested_quantities(), vec![0, 10]);
+
+        // Without purchases in the ledger, AGGG.L is valued at the amount entered for it.
+        with_db(|db| {
+            let purchase = db.list_purchases()?.into_iter().find(|purchase| purchase.etf_id == "AGGG.L").unwrap();
+            db.undo_purchase(purchase.id)?;
+            db.update_cumulative("AGGG.L", 3_000)?;
+            Ok(())
+        }).unwrap();
+        assert_eq!(suggested_quantities(), vec![5, 0]);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_suggest_investments_explained() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("explained");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(1_000, vec![
+            EtfSetting::new("IUSE.L".into(), "IE00B3ZW0K18".into(), "iShares S&P 500 EUR Hedged".into(), 0.75, 3_000),
+            EtfSetting::new("AGGG.L".into(), "IE00B3F81409".into(), "iShares Core Global Aggregate Bond".into(), 0.25, 1_000),
+        ]));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+
+        set_price_provider(Arc::new(FixturePriceProvider::new().with_price("IUSE.L", 3.0).with_price("AGGG.L", 0.5)));
+        let suggestions = etfplan_suggest_investments_explained();
+        assert_eq!(etfplan_last_error_code(), CErrorCode::Ok);
+        let explained = unsafe { std::slice::from_raw_parts(suggestions.suggestions, suggestions.length) }
+            .iter()
+            .map(|s| (s.investment.quantity, s.current_proportion, s.ideal_proportion, s.target, s.post_purchase_proportion, s.residual_error))
+            .collect::<Vec<_>>();
+        assert_eq!(explained, vec![
+            (2, 0.75, 0.75, 3_750, 3_600.0 / 4_850.0, 150.0 * 150.0),
+            (5, 0.25, 0.25, 1_250, 1_250.0 / 4_850.0, 0.0),
+        ]);
+        assert_eq!(suggestions.left_over, 150);
+        assert_eq!(suggestions.total_fees, 0);
+        etfplan_free_suggestions(suggestions);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_export_and_import_plan() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("export-plan");
+        assert_eq!(etfplan_init(db_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        let settings = CSettings::from(Settings::new(50_000, etf_settings()));
+        assert_eq!(etfplan_persist_settings(&settings), CErrorCode::Ok);
+        etfplan_free_settings(Box::into_raw(Box::new(settings)));
+        let document = etfplan_export_plan(CPlanFormat::Toml as i32);
+        assert_eq!(etfplan_last_error_code(), CErrorCode::Ok);
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+
+        let other_path = temp_db_path("import-plan");
+        assert_eq!(etfplan_init(other_path.as_ptr(), std::ptr::null()), CErrorCode::Ok);
+        assert_eq!(etfplan_import_plan(c"{\"version\": 99}".as_ptr(), CPlanFormat::Json as i32), CErrorCode::InvalidArgument);
+        assert!(etfplan_export_plan(-1).is_null());
+        assert_eq!(etfplan_last_error_code(), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_import_plan(document, 2), CErrorCode::InvalidArgument);
+        assert_eq!(etfplan_import_plan(document, CPlanFormat::Toml as i32), CErrorCode::Ok);
+        etfplan_free_string(document);
+        assert_eq!(get_settings_from_db().unwrap(), Settings::new(50_000, etf_settings()));
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+}
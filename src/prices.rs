@@ -0,0 +1,121 @@
+use std::sync::{PoisonError, RwLock};
+use database::PriceData;
+use derive_new::new;
+use futures::future;
+use yahoo_finance_info::{fx_ticker, PriceProvider};
+
+use crate::error::Error;
+use crate::{with_db, RT};
+
+#[derive(Debug, Clone, Copy, PartialEq, new)]
+pub(crate) struct PriceCachePolicy {
+    // Cached quotes younger than this are used without asking the provider.
+    pub refresh_after_seconds: i64,
+    // When the provider fails, cached quotes up to this age are used instead and marked stale.
+    pub max_age_seconds: i64,
+}
+
+impl Default for PriceCachePolicy {
+    fn default() -> Self {
+        // Three days, so Friday's closing prices still count over the weekend.
+        PriceCachePolicy::new(15 * 60, 3 * 24 * 60 * 60)
+    }
+}
+
+static PRICE_CACHE_POLICY: RwLock<Option<PriceCachePolicy>> = RwLock::new(None);
+
+pub(crate) fn set_price_cache_policy(policy: PriceCachePolicy) {
+    *PRICE_CACHE_POLICY.write().unwrap_or_else(PoisonError::into_inner) = Some(policy);
+}
+
+fn price_cache_policy() -> PriceCachePolicy {
+    PRICE_CACHE_POLICY.read().unwrap_or_else(PoisonError::into_inner).unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, new)]
+pub struct CachedPrice {
+    pub price: f64,
+    pub currency: Option<String>,
+    // Unix time in seconds.
+    pub fetched_at: i64,
+    pub is_stale: bool,
+}
+
+impl From<PriceData> for CachedPrice {
+    fn from(price: PriceData) -> Self {
+        CachedPrice::new(price.price, price.currency, price.fetched_at, false)
+    }
+}
+
+// Looks up the prices of all tickers, in order, going to the provider only for the ones whose cached quote
+// is missing or older than the policy allows. The database is not locked while the provider is asked.
+pub(crate) fn get_cached_prices(provider: &dyn PriceProvider, tickers: &[String]) -> Result<Vec<CachedPrice>, Error> {
+    let policy = price_cache_policy();
+    let now = chrono::Utc::now().timestamp();
+
+    let cached = with_db(|db| tickers.iter().map(|ticker| Ok(db.get_price(ticker)?)).collect::<Result<Vec<_>, Error>>())?;
+    let is_fresh = |price: &Option<PriceData>| price.as_ref().is_some_and(|price| now - price.fetched_at < policy.refresh_after_seconds);
+
+    let futures = tickers.iter().zip(&cached)
+        .filter(|(_, price)| !is_fresh(price))
+        .map(|(ticker, _)| provider.latest_quote(ticker));
+    let mut quotes = RT.block_on(future::join_all(futures)).into_iter();
+
+    let mut prices = vec![];
+    let mut fetched = vec![];
+    for (ticker, cached) in tickers.iter().zip(cached) {
+        if is_fresh(&cached) {
+            prices.push(CachedPrice::from(cached.expect("fresh prices are cached")));
+            continue;
+        }
+        match (quotes.next().expect("a quote was requested for every price that is not fresh"), cached) {
+            (Ok(quote), _) => {
+                let quote = quote.in_major_unit();
+                let price = PriceData::new(ticker.clone(), quote.price, quote.currency, now);
+                prices.push(CachedPrice::from(price.clone()));
+                fetched.push(price);
+            }
+            (Err(_), Some(cached)) if now - cached.fetched_at <= policy.max_age_seconds => {
+                prices.push(CachedPrice { is_stale: true, ..CachedPrice::from(cached) });
+            }
+            (Err(e), _) => return Err(e.into()),
+        }
+    }
+
+    with_db(|db| db.transaction(|db| {
+        for price in fetched {
+            db.store_price(price)?;
+        }
+        Ok(())
+    }))?;
+    Ok(prices)
+}
+
+// Converts the prices into `currency`, looking up the exchange rates through the same cache as the prices.
+// A price without a known quote currency is taken to be in `currency` already.
+pub(crate) fn convert_prices(provider: &dyn PriceProvider, prices: Vec<CachedPrice>, currency: &str) -> Result<Vec<CachedPrice>, Error> {
+    let mut foreign_currencies = prices.iter()
+        .filter_map(|price| price.currency.clone())
+        .filter(|quote_currency| quote_currency != currency)
+        .collect::<Vec<_>>();
+    foreign_currencies.sort();
+    foreign_currencies.dedup();
+
+    let fx_tickers = foreign_currencies.iter().map(|from| fx_ticker(from, currency)).collect::<Vec<_>>();
+    let rates = get_cached_prices(provider, &fx_tickers)?;
+
+    Ok(prices.into_iter().map(|price| {
+        let rate = price.currency.as_ref()
+            .and_then(|quote_currency| foreign_currencies.iter().position(|from| from == quote_currency))
+            .map(|i| &rates[i]);
+        match rate {
+            None => CachedPrice { currency: Some(currency.to_string()), ..price },
+            Some(rate) => CachedPrice::new(
+                price.price * rate.price,
+                Some(currency.to_string()),
+                price.fetched_at.min(rate.fetched_at),
+                price.is_stale || rate.is_stale,
+            ),
+        }
+    }).collect())
+}
@@ -0,0 +1,341 @@
+// The functions of the C header over HTTP with JSON bodies, for the web dashboard. Amounts are in cents.
+
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use investment_planner::{drift_report, left_over_cash, total_fees, Action, EtfSetting, Fee, OrderLimits, Settings};
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+use yahoo_finance_info::YahooError;
+
+use crate::{find_etf, get_prices, get_settings_from_db, store_settings, suggest, Error, Plan};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EtfSettingBody {
+    pub id: String,
+    pub isin: String,
+    pub name: String,
+    pub ideal_proportion: f64,
+    // What is already invested in the etf.
+    pub cumulative: i64,
+    #[serde(default)]
+    pub fee_fixed: i64,
+    #[serde(default)]
+    pub fee_basis_points: i64,
+    #[serde(default)]
+    pub min_quantity: i64,
+    #[serde(default)]
+    pub max_quantity: Option<i64>,
+    // 0 is taken as 1, i.e. no lots.
+    #[serde(default)]
+    pub lot_size: i64,
+    #[serde(default)]
+    pub min_order_amount: i64,
+}
+impl From<EtfSetting> for EtfSettingBody {
+    fn from(etf: EtfSetting) -> Self {
+        EtfSettingBody {
+            id: etf.id, isin: etf.isin, name: etf.name, ideal_proportion: etf.ideal_proportion, cumulative: etf.cumulative,
+            fee_fixed: etf.fee.fixed, fee_basis_points: etf.fee.basis_points,
+            min_quantity: etf.limits.min_quantity, max_quantity: etf.limits.max_quantity, lot_size: etf.limits.lot_size,
+            min_order_amount: etf.limits.min_amount,
+        }
+    }
+}
+impl EtfSettingBody {
+    fn etf_setting(self) -> Result<EtfSetting, Error> {
+        if self.ideal_proportion.is_nan() || self.ideal_proportion < 0.0 || self.cumulative < 0 {
+            return Err(Error::InvalidArgument(format!("the proportion and cumulative amount of {} cannot be negative", self.id)));
+        }
+        if self.fee_fixed < 0 || self.fee_basis_points < 0 {
+            return Err(Error::InvalidArgument("fees cannot be negative".to_string()));
+        }
+        if self.min_quantity < 0 || self.max_quantity.is_some_and(|max| max < 0) || self.lot_size < 0 || self.min_order_amount < 0 {
+            return Err(Error::InvalidArgument("order limits cannot be negative".to_string()));
+        }
+        Ok(EtfSetting::new(self.id, self.isin, self.name, self.ideal_proportion, self.cumulative)
+            .with_fee(Fee::new(self.fee_fixed, self.fee_basis_points))
+            .with_limits(OrderLimits::new(self.min_quantity, self.max_quantity, self.lot_size.max(1), self.min_order_amount)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SettingsBody {
+    pub budget: i64,
+    pub etfs: Vec<EtfSettingBody>,
+}
+impl From<Settings> for SettingsBody {
+    fn from(settings: Settings) -> Self {
+        SettingsBody { budget: settings.budget, etfs: settings.etf_settings.into_iter().map(EtfSettingBody::from).collect() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EtfInfoBody {
+    pub ticker: String,
+    pub isin: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PriceBody {
+    pub ticker: String,
+    // In cents of the budget currency.
+    pub price: f64,
+    pub currency: Option<String>,
+    // Unix time in seconds at which the price was fetched from the provider.
+    pub fetched_at: i64,
+    // Set when the provider could not be reached and an older cached price was used instead.
+    pub is_stale: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionBody {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SuggestionBody {
+    pub etf_id: String,
+    pub name: String,
+    pub action: ActionBody,
+    // In units of 10^-quantity_decimals shares.
+    pub quantity: i64,
+    pub quantity_decimals: u32,
+    pub price: i64,
+    pub price_is_stale: bool,
+    pub fee: i64,
+    pub current_proportion: f64,
+    pub ideal_proportion: f64,
+    pub post_purchase_proportion: f64,
+    pub target: i64,
+    pub residual_error: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SuggestionsBody {
+    pub suggestions: Vec<SuggestionBody>,
+    pub total_fees: i64,
+    // What is left of the budget after buying, selling and paying the fees.
+    pub left_over: i64,
+}
+impl From<Plan> for SuggestionsBody {
+    fn from(plan: Plan) -> Self {
+        let investments = plan.investments.iter().map(|(investment, _)| investment.clone()).collect::<Vec<_>>();
+        let drifts = drift_report(&plan.settings, &plan.prices, &investments);
+        let total_fees = total_fees(&investments);
+        let left_over = left_over_cash(plan.settings.budget, &investments, &plan.prices);
+
+        let suggestions = plan.investments.into_iter().map(|(investment, price)| {
+            let i = plan.settings.etf_settings.iter().position(|etf| etf.id == investment.etf_id).expect("investments are in configured etfs");
+            let drift = &drifts[i];
+            SuggestionBody {
+                action: match investment.action {
+                    Action::Buy => ActionBody::Buy,
+                    Action::Sell => ActionBody::Sell,
+                },
+                etf_id: investment.etf_id, name: investment.name, quantity: investment.quantity, quantity_decimals: investment.decimals,
+                price: investment.price, price_is_stale: price.is_stale, fee: investment.fee,
+                current_proportion: drift.current_proportion, ideal_proportion: drift.ideal_proportion,
+                post_purchase_proportion: drift.post_purchase_proportion, target: drift.target, residual_error: drift.residual_error,
+            }
+        }).collect();
+        SuggestionsBody { suggestions, total_fees, left_over }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorBody {
+    // The same codes as CErrorCode.
+    pub code: i32,
+    pub message: String,
+}
+
+pub struct ApiError(Error);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = match &self.0 {
+            Error::InvalidArgument(_) | Error::Parse(_) => StatusCode::BAD_REQUEST,
+            Error::EtfNotFound(_) => StatusCode::NOT_FOUND,
+            Error::AmbiguousIsin(_) | Error::NoBudget => StatusCode::CONFLICT,
+            Error::Yahoo(YahooError::FetchFailed(_) | YahooError::ConnectionFailed(_) | YahooError::BuilderFailed) => StatusCode::BAD_GATEWAY,
+            Error::NotInitialized | Error::Sqlite(_) | Error::Yahoo(_) | Error::Panic(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        (status, Json(ErrorBody { code: self.0.code() as i32, message: self.0.to_string() })).into_response()
+    }
+}
+
+// The library blocks on its own runtime and holds the database lock, so it runs off the server's workers.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<Json<T>, ApiError> {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(result) => result.map(Json).map_err(ApiError),
+        Err(e) => Err(ApiError(Error::Panic(e.to_string()))),
+    }
+}
+
+#[utoipa::path(get, path = "/settings", responses(
+    (status = 200, body = SettingsBody),
+    (status = 409, description = "No budget is set", body = ErrorBody),
+))]
+async fn get_settings() -> Result<Json<SettingsBody>, ApiError> {
+    blocking(|| Ok(get_settings_from_db()?.into())).await
+}
+
+#[utoipa::path(put, path = "/settings", request_body = SettingsBody, responses(
+    (status = 200, body = SettingsBody),
+    (status = 400, description = "Negative amounts or limits", body = ErrorBody),
+))]
+async fn put_settings(Json(body): Json<SettingsBody>) -> Result<Json<SettingsBody>, ApiError> {
+    blocking(move || {
+        if body.budget < 0 {
+            return Err(Error::InvalidArgument("the budget cannot be negative".to_string()));
+        }
+        let etf_settings = body.etfs.iter().cloned().map(EtfSettingBody::etf_setting).collect::<Result<Vec<_>, _>>()?;
+        store_settings(Settings::new(body.budget, etf_settings))?;
+        // What was stored, e.g. with lot size 0 taken as 1, rather than the request.
+        Ok(get_settings_from_db()?.into())
+    }).await
+}
+
+#[utoipa::path(get, path = "/etfs/{isin}", params(("isin" = String, Path)), responses(
+    (status = 200, body = EtfInfoBody),
+    (status = 404, description = "No etf has this isin", body = ErrorBody),
+    (status = 409, description = "More than one etf has this isin", body = ErrorBody),
+))]
+async fn get_etf(Path(isin): Path<String>) -> Result<Json<EtfInfoBody>, ApiError> {
+    blocking(move || {
+        let etf = find_etf(&isin)?;
+        Ok(EtfInfoBody { ticker: etf.ticker, isin: etf.isin, name: etf.name })
+    }).await
+}
+
+#[utoipa::path(get, path = "/prices", responses(
+    (status = 200, description = "The prices of the configured etfs, in their order", body = Vec<PriceBody>),
+))]
+async fn get_etf_prices() -> Result<Json<Vec<PriceBody>>, ApiError> {
+    blocking(|| {
+        let tickers = get_settings_from_db()?.etf_settings.into_iter().map(|etf| etf.id).collect::<Vec<_>>();
+        let prices = get_prices(&tickers)?;
+        Ok(tickers.into_iter().zip(prices).map(|(ticker, price)| PriceBody {
+            ticker,
+            price: price.price * 100.0 /* convert to cents */,
+            currency: price.currency,
+            fetched_at: price.fetched_at,
+            is_stale: price.is_stale,
+        }).collect())
+    }).await
+}
+
+#[utoipa::path(get, path = "/suggestions", responses(
+    (status = 200, body = SuggestionsBody),
+    (status = 409, description = "No budget is set", body = ErrorBody),
+))]
+async fn get_suggestions() -> Result<Json<SuggestionsBody>, ApiError> {
+    blocking(|| suggest().map(SuggestionsBody::from)).await
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "etf investment plan", description = "Suggests etf investments towards ideal proportions. Amounts are in cents."),
+    paths(get_settings, put_settings, get_etf, get_etf_prices, get_suggestions),
+)]
+pub struct ApiDoc;
+
+async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+// The routes, for a database opened and a price provider set beforehand.
+pub fn router() -> Router {
+    Router::new()
+        .route("/settings", get(get_settings).put(put_settings))
+        .route("/etfs/{isin}", get(get_etf))
+        .route("/prices", get(get_etf_prices))
+        .route("/suggestions", get(get_suggestions))
+        .route("/openapi.json", get(openapi))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, PoisonError};
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use database::DatabaseOptions;
+    use serde::de::DeserializeOwned;
+    use tower::ServiceExt;
+    use yahoo_finance_info::FixturePriceProvider;
+    use crate::tests::{temp_db_path, GLOBALS};
+    use crate::{etfplan_shutdown, open_database, set_price_provider, CErrorCode};
+
+    fn request<T: DeserializeOwned>(method: &str, uri: &str, body: Option<String>) -> (StatusCode, T) {
+        let request = Request::builder().method(method).uri(uri).header("content-type", "application/json")
+            .body(body.map(Body::from).unwrap_or_default())
+            .unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let response = router().oneshot(request).await.unwrap();
+            let status = response.status();
+            let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            (status, serde_json::from_slice(&bytes).unwrap())
+        })
+    }
+
+    #[test]
+    fn test_api_with_fixture_prices() {
+        let _globals = GLOBALS.lock().unwrap_or_else(PoisonError::into_inner);
+        let db_path = temp_db_path("server");
+        open_database(db_path.to_str().unwrap(), DatabaseOptions::default()).unwrap();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/yahoo-finance-info/fixtures/prices.json");
+        set_price_provider(Arc::new(FixturePriceProvider::from_file(fixtures).unwrap()));
+
+        let (status, error) = request::<ErrorBody>("GET", "/suggestions", None);
+        assert_eq!((status, error.code), (StatusCode::CONFLICT, CErrorCode::NoBudget as i32));
+
+        let (status, etf) = request::<EtfInfoBody>("GET", "/etfs/IE00B3ZW0K18", None);
+        assert_eq!((status, etf.ticker.as_str()), (StatusCode::OK, "IUSE.L"));
+        let (status, _) = request::<ErrorBody>("GET", "/etfs/XX0000000000", None);
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let etf = |id: &str, isin: &str| EtfSettingBody {
+            id: id.to_string(), isin: isin.to_string(), name: String::new(), ideal_proportion: 0.5, cumulative: 0,
+            fee_fixed: 0, fee_basis_points: 0, min_quantity: 0, max_quantity: None, lot_size: 1, min_order_amount: 0,
+        };
+        let settings = SettingsBody { budget: 50_000, etfs: vec![etf("IUSE.L", "IE00B3ZW0K18"), etf("AGGG.L", "IE00B3F81409")] };
+        let (status, _) = request::<SettingsBody>("PUT", "/settings", Some(serde_json::to_string(&settings).unwrap()));
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(request::<SettingsBody>("GET", "/settings", None), (StatusCode::OK, settings.clone()));
+
+        let negative = SettingsBody { budget: -1, ..settings };
+        let (status, _) = request::<ErrorBody>("PUT", "/settings", Some(serde_json::to_string(&negative).unwrap()));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, prices) = request::<Vec<PriceBody>>("GET", "/prices", None);
+        assert_eq!(prices.iter().map(|price| (price.ticker.as_str(), price.price.round())).collect::<Vec<_>>(), vec![
+            ("IUSE.L", 11_236.0),
+            ("AGGG.L", 487.0),
+        ]);
+
+        let (status, suggestions) = request::<SuggestionsBody>("GET", "/suggestions", None);
+        assert_eq!(status, StatusCode::OK);
+        let quantities = suggestions.suggestions.iter().map(|s| (s.etf_id.as_str(), s.action, s.quantity)).collect::<Vec<_>>();
+        assert_eq!(quantities, vec![("IUSE.L", ActionBody::Buy, 2), ("AGGG.L", ActionBody::Buy, 51)]);
+        assert_eq!(suggestions.left_over, 50_000 - 2 * 11_236 - 51 * 487);
+
+        assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    }
+
+    #[test]
+    fn test_openapi_describes_every_route() {
+        let (status, openapi) = request::<serde_json::Value>("GET", "/openapi.json", None);
+        assert_eq!(status, StatusCode::OK);
+        let paths = openapi["paths"].as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        assert_eq!(paths, vec!["/etfs/{isin}", "/prices", "/settings", "/suggestions"]);
+        assert!(openapi["components"]["schemas"]["SuggestionsBody"].is_object());
+    }
+}
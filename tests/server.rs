@@ -0,0 +1,66 @@
+// Serves the routes on a local port and talks to them over TCP, like the web dashboard does.
+#![cfg(feature = "server")]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use database::DatabaseOptions;
+use etfinvestmentplan::server::{router, SettingsBody, SuggestionsBody};
+use etfinvestmentplan::{etfplan_shutdown, open_database, set_price_provider, CErrorCode};
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use yahoo_finance_info::FixturePriceProvider;
+
+// Sends one request on a connection of its own and reads the response until the server closes it.
+async fn request<T: DeserializeOwned>(address: SocketAddr, method: &str, uri: &str, body: &str) -> (u16, T) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{method} {uri} HTTP/1.1\r\nHost: {address}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len(),
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_serve_over_tcp() {
+    // Unique to this run, so concurrent runs do not share a database.
+    let db_path = std::env::temp_dir().join(format!("etf-investment-plan-server-tcp-{}-db", std::process::id()));
+    let _ = std::fs::remove_file(&db_path);
+    open_database(db_path.to_str().unwrap(), DatabaseOptions::default()).unwrap();
+    let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/yahoo-finance-info/fixtures/prices.json");
+    set_price_provider(Arc::new(FixturePriceProvider::from_file(fixtures).unwrap()));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move { axum::serve(listener, router()).await });
+
+    let settings = serde_json::json!({
+        "budget": 50_000,
+        "etfs": [
+            { "id": "IUSE.L", "isin": "IE00B3ZW0K18", "name": "", "ideal_proportion": 0.5, "cumulative": 0 },
+            { "id": "AGGG.L", "isin": "IE00B3F81409", "name": "", "ideal_proportion": 0.5, "cumulative": 0 },
+        ],
+    });
+    let (status, stored) = request::<SettingsBody>(address, "PUT", "/settings", &settings.to_string()).await;
+    assert_eq!((status, stored.budget), (200, 50_000));
+    let etfs = stored.etfs.iter().map(|etf| (etf.id.as_str(), etf.ideal_proportion, etf.lot_size)).collect::<Vec<_>>();
+    assert_eq!(etfs, vec![("IUSE.L", 0.5, 1), ("AGGG.L", 0.5, 1)]);
+
+    let (status, read) = request::<SettingsBody>(address, "GET", "/settings", "").await;
+    assert_eq!((status, read), (200, stored));
+
+    let (status, suggestions) = request::<SuggestionsBody>(address, "GET", "/suggestions", "").await;
+    assert_eq!(status, 200);
+    let quantities = suggestions.suggestions.iter().map(|s| (s.etf_id.as_str(), s.quantity)).collect::<Vec<_>>();
+    assert_eq!(quantities, vec![("IUSE.L", 2), ("AGGG.L", 51)]);
+
+    server.abort();
+    assert_eq!(etfplan_shutdown(), CErrorCode::Ok);
+    std::fs::remove_file(&db_path).unwrap();
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::path::Path;
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::{History, Interval, Isin, PriceProvider, Quote, Ticker, YahooError, ETF};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum FixtureQuote {
+    Price(f64),
+    Quote(Quote),
+}
+impl From<FixtureQuote> for Quote {
+    fn from(quote: FixtureQuote) -> Self {
+        match quote {
+            FixtureQuote::Price(price) => Quote::new(price, None),
+            FixtureQuote::Quote(quote) => quote,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Fixtures {
+    #[serde(default)]
+    etfs: Vec<ETF>,
+    #[serde(default)]
+    prices: HashMap<Ticker, FixtureQuote>,
+    #[serde(default)]
+    history: HashMap<Ticker, History>,
+}
+
+// Answers from data held in memory, for running offline. The fixture file is JSON of the form
+// {"etfs": [{"name": ..., "isin": ..., "ticker": ...}], "prices": {"<ticker>": <price>}}, where a price
+// can also be given with its currency as {"price": <price>, "currency": "<currency>"}. An optional "history" maps
+// tickers to {"bars": [...], "dividends": [...]}, which is served as is for every interval.
+#[derive(Debug, Default)]
+pub struct FixturePriceProvider {
+    fixtures: Fixtures,
+}
+
+impl FixturePriceProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, YahooError> {
+        let json = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| YahooError::FetchFailed(format!("could not read {}: {e}", path.as_ref().display())))?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, YahooError> {
+        Ok(Self { fixtures: serde_json::from_str(json)? })
+    }
+
+    pub fn with_etf(mut self, etf: ETF) -> Self {
+        self.fixtures.etfs.push(etf);
+        self
+    }
+
+    pub fn with_price(mut self, ticker: &str, price: f64) -> Self {
+        self.fixtures.prices.insert(ticker.to_string(), FixtureQuote::Price(price));
+        self
+    }
+
+    pub fn with_quote(mut self, ticker: &str, quote: Quote) -> Self {
+        self.fixtures.prices.insert(ticker.to_string(), FixtureQuote::Quote(quote));
+        self
+    }
+
+    pub fn with_history(mut self, ticker: &str, history: History) -> Self {
+        self.fixtures.history.insert(ticker.to_string(), history);
+        self
+    }
+}
+
+#[async_trait]
+impl PriceProvider for FixturePriceProvider {
+    async fn search_by_isin(&self, isin: &Isin) -> Result<Vec<ETF>, YahooError> {
+        Ok(self.fixtures.etfs.iter().filter(|etf| &etf.isin == isin).cloned().collect())
+    }
+
+    async fn latest_quote(&self, ticker: &Ticker) -> Result<Quote, YahooError> {
+        self.fixtures.prices.get(ticker).cloned().map(Quote::from).ok_or(YahooError::EmptyDataSet)
+    }
+
+    async fn history(&self, ticker: &Ticker, start: i64, end: i64, _interval: Interval) -> Result<History, YahooError> {
+        self.fixtures.history.get(ticker).map(|history| history.between(start, end)).ok_or(YahooError::EmptyDataSet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Dividend;
+
+    #[tokio::test]
+    async fn test_fixture_file() {
+        let provider = FixturePriceProvider::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/prices.json")).unwrap();
+
+        let xs = provider.search_by_isin(&"IE00B3ZW0K18".to_string()).await.unwrap();
+        assert_eq!(xs, vec![ETF::new("iShares S&P 500 EUR Hedged UCITS ETF (Acc)".to_string(), "IE00B3ZW0K18".into(), "IUSE.L".into())]);
+        assert_eq!(provider.latest_price(&"IUSE.L".to_string()).await.unwrap(), 112.36);
+        assert_eq!(provider.latest_quote(&"IWDA.AS".to_string()).await.unwrap(), Quote::new(98.12, Some("EUR".into())));
+
+        let history = provider.history(&"IUSE.L".to_string(), 1_735_776_000, 1_736_121_600, Interval::Day).await.unwrap();
+        assert_eq!(history.bars.len(), 3);
+        assert_eq!(history.bars[0].adj_close, 110.02);
+        assert_eq!(history.dividends, vec![]);
+        let history = provider.history(&"AGGG.L".to_string(), 0, i64::MAX, Interval::Day).await.unwrap();
+        assert_eq!(history.dividends, vec![Dividend::new(1_736_121_600, 0.05)]);
+    }
+
+    #[tokio::test]
+    async fn test_in_memory() {
+        let provider = FixturePriceProvider::new()
+            .with_etf(ETF::new("NAME".into(), "ISIN".into(), "TICKER".into()))
+            .with_price("TICKER", 5.0);
+
+        assert_eq!(provider.search_by_isin(&"ISIN".to_string()).await.unwrap().len(), 1);
+        assert!(provider.search_by_isin(&"OTHER".to_string()).await.unwrap().is_empty());
+        assert_eq!(provider.latest_price(&"TICKER".to_string()).await.unwrap(), 5.0);
+        assert!(provider.latest_price(&"OTHER".to_string()).await.is_err());
+    }
+}
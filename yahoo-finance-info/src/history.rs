@@ -0,0 +1,74 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    // The name Yahoo Finance uses, which is also how the interval is stored.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Interval::Day => "1d",
+            Interval::Week => "1wk",
+            Interval::Month => "1mo",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PriceBar {
+    // Unix time in seconds at which the bar starts.
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    // The close adjusted for dividends and splits, for comparing returns over time.
+    pub adj_close: f64,
+    pub volume: u64,
+}
+
+impl PriceBar {
+    pub fn new(timestamp: i64, open: f64, high: f64, low: f64, close: f64, adj_close: f64, volume: u64) -> Self {
+        Self { timestamp, open, high, low, close, adj_close, volume }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Dividend {
+    // Unix time in seconds of the ex-dividend date.
+    pub timestamp: i64,
+    pub amount: f64,
+}
+
+impl Dividend {
+    pub fn new(timestamp: i64, amount: f64) -> Self {
+        Self { timestamp, amount }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    pub bars: Vec<PriceBar>,
+    #[serde(default)]
+    pub dividends: Vec<Dividend>,
+}
+
+impl History {
+    pub fn new(bars: Vec<PriceBar>, dividends: Vec<Dividend>) -> Self {
+        Self { bars, dividends }
+    }
+
+    // Keeps what falls within start and end, both inclusive.
+    pub fn between(&self, start: i64, end: i64) -> History {
+        let in_range = |timestamp: i64| start <= timestamp && timestamp <= end;
+        History::new(
+            self.bars.iter().filter(|bar| in_range(bar.timestamp)).cloned().collect(),
+            self.dividends.iter().filter(|dividend| in_range(dividend.timestamp)).cloned().collect(),
+        )
+    }
+}
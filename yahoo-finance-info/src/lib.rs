@@ -1,10 +1,18 @@
+mod fixture;
+mod history;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use yahoo_finance_api as yahoo;
 pub use yahoo_finance_api::YahooError;
 
+pub use fixture::FixturePriceProvider;
+pub use history::{Dividend, History, Interval, PriceBar};
+
 pub type Isin = String;
 pub type Ticker = String;
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ETF {
     pub name: String,
     pub isin: Isin,
@@ -17,26 +25,127 @@ impl ETF {
     }
 }
 
-pub async fn search_etf_isin(isin: &Isin) -> Result<Vec<ETF>, YahooError> {
-    let provider = yahoo::YahooConnector::new()?;
-    let resp = provider.search_ticker(&isin).await?;
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quote {
+    pub price: f64,
+    pub currency: Option<String>,
+}
+
+impl Quote {
+    pub fn new(price: f64, currency: Option<String>) -> Self {
+        Self { price, currency }
+    }
+
+    // Yahoo quotes London and Johannesburg listings in pence and cents and Tel Aviv ones in agorot,
+    // which this turns into pounds, rand and shekels.
+    pub fn in_major_unit(self) -> Quote {
+        match self.currency.as_deref() {
+            Some("GBp" | "GBX") => Quote::new(self.price / 100.0, Some("GBP".to_string())),
+            Some("ZAc" | "ZAC") => Quote::new(self.price / 100.0, Some("ZAR".to_string())),
+            Some("ILA") => Quote::new(self.price / 100.0, Some("ILS".to_string())),
+            _ => self,
+        }
+    }
+}
+
+// The ticker under which Yahoo Finance quotes how much one unit of `from` is worth in `to`.
+pub fn fx_ticker(from: &str, to: &str) -> Ticker {
+    format!("{from}{to}=X")
+}
+
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    async fn search_by_isin(&self, isin: &Isin) -> Result<Vec<ETF>, YahooError>;
+    async fn latest_quote(&self, ticker: &Ticker) -> Result<Quote, YahooError>;
+    // Start and end are unix times in seconds, both inclusive.
+    async fn history(&self, ticker: &Ticker, start: i64, end: i64, interval: Interval) -> Result<History, YahooError>;
+
+    async fn latest_price(&self, ticker: &Ticker) -> Result<f64, YahooError> {
+        Ok(self.latest_quote(ticker).await?.price)
+    }
+
+    async fn fx_rate(&self, from: &str, to: &str) -> Result<f64, YahooError> {
+        if from == to {
+            return Ok(1.0);
+        }
+        self.latest_price(&fx_ticker(from, to)).await
+    }
+}
 
-    Ok(resp.quotes.into_iter().map(|quote| {
-        ETF::new(quote.long_name, isin.clone(), quote.symbol)
-    }).collect())
+pub struct YahooPriceProvider {
+    connector: yahoo::YahooConnector,
+}
+
+impl YahooPriceProvider {
+    pub fn new() -> Result<Self, YahooError> {
+        Ok(Self { connector: yahoo::YahooConnector::new()? })
+    }
+}
+
+#[async_trait]
+impl PriceProvider for YahooPriceProvider {
+    async fn search_by_isin(&self, isin: &Isin) -> Result<Vec<ETF>, YahooError> {
+        let resp = self.connector.search_ticker(isin).await?;
+
+        Ok(resp.quotes.into_iter().map(|quote| {
+            ETF::new(quote.long_name, isin.clone(), quote.symbol)
+        }).collect())
+    }
+
+    async fn latest_quote(&self, ticker: &Ticker) -> Result<Quote, YahooError> {
+        let response = self.connector.get_latest_quotes(ticker, "1d").await?;
+        let quote = response.last_quote()?;
+        Ok(Quote::new(quote.close, response.metadata()?.currency))
+    }
+
+    async fn history(&self, ticker: &Ticker, start: i64, end: i64, interval: Interval) -> Result<History, YahooError> {
+        let to_date_time = |timestamp: i64| yahoo::time::OffsetDateTime::from_unix_timestamp(timestamp)
+            .map_err(|e| YahooError::FetchFailed(format!("invalid timestamp {timestamp}: {e}")));
+        let response = self.connector
+            .get_quote_history_interval(ticker, to_date_time(start)?, to_date_time(end)?, interval.as_str())
+            .await?;
+
+        let bars = response.quotes()?.into_iter().map(|quote| {
+            PriceBar::new(quote.timestamp as i64, quote.open, quote.high, quote.low, quote.close, quote.adjclose, quote.volume)
+        }).collect();
+        let dividends = response.dividends()?.into_iter().map(|dividend| {
+            Dividend::new(dividend.date as i64, dividend.amount)
+        }).collect();
+        Ok(History::new(bars, dividends))
+    }
+}
+
+pub async fn search_etf_isin(isin: &Isin) -> Result<Vec<ETF>, YahooError> {
+    YahooPriceProvider::new()?.search_by_isin(isin).await
 }
 
 pub async fn get_price_of(ticker: &Ticker) -> Result<f64, YahooError> {
-    let provider = yahoo::YahooConnector::new()?;
-    let response = provider.get_latest_quotes(&ticker, "1d").await?;
-    let quote = response.last_quote()?;
-    Ok(quote.close)
+    YahooPriceProvider::new()?.latest_price(ticker).await
+}
+
+pub async fn get_history(ticker: &Ticker, start: i64, end: i64, interval: Interval) -> Result<History, YahooError> {
+    YahooPriceProvider::new()?.history(ticker, start, end, interval).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_quote_in_major_unit() {
+        assert_eq!(Quote::new(11236.0, Some("GBp".into())).in_major_unit(), Quote::new(112.36, Some("GBP".into())));
+        assert_eq!(Quote::new(98.12, Some("EUR".into())).in_major_unit(), Quote::new(98.12, Some("EUR".into())));
+        assert_eq!(Quote::new(4.87, None).in_major_unit(), Quote::new(4.87, None));
+    }
+
+    #[tokio::test]
+    async fn test_fx_rate_from_fixture() {
+        let provider = FixturePriceProvider::new().with_price("GBPEUR=X", 1.19);
+        assert_eq!(provider.fx_rate("GBP", "EUR").await.unwrap(), 1.19);
+        assert_eq!(provider.fx_rate("EUR", "EUR").await.unwrap(), 1.0);
+        assert!(provider.fx_rate("USD", "EUR").await.is_err());
+    }
+
     #[tokio::test]
     async fn test_search_etf_isin() {
         let xs = search_etf_isin(&"IE00B3ZW0K18".to_string()).await.unwrap();